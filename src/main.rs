@@ -1,68 +1,590 @@
-use clap::{Parser, Subcommand}; // Import two traits from the clap crate
+use clap::{Args, Parser, Subcommand, ValueEnum}; // Import four traits from the clap crate
 use serde::{Serialize, Deserialize}; // Import two traits from the serde crate
 use std::fs; // Import the fs module from the standard library for file operations
-use std::path::Path; // Import the Path type for working with file paths
+use std::path::{Path, PathBuf}; // Import Path (a borrowed file path) and PathBuf (an owned one we can build up)
 use reqwest; // Import reqwest for making HTTP requests to the Ollama API
 use futures_util::StreamExt; // Import StreamExt trait to work with async streams (enables .next() method on streams) 
 use std::io::{self, Write}; // Import io utilities for reading user input and flushing output to display text immediately
+use std::time::{Duration, Instant}; // Duration describes timeouts (e.g. Duration::from_secs(30)), Instant measures elapsed time
+use std::io::IsTerminal; // Import IsTerminal to check if output goes to a terminal or is piped/redirected
+use std::sync::OnceLock; // Import OnceLock for a global value that is set once at startup
 
-// Name of the file where tasks are stored. 
+// Name of the file where tasks are stored (inside data_dir(), unless DOIT_TASKS_FILE says otherwise).
 // This is known at compile time, stored in the binary, and lives for the entire program duration.
 const TASKS_FILE: &str = "tasks.json";
 
+// How long we try to connect to Ollama before giving up (seconds), unless --connect-timeout says otherwise
+// Without a limit, a wrong DOIT_OLLAMA_URL pointing at an unreachable machine could hang for minutes
+const DEFAULT_CONNECT_TIMEOUT: u64 = 30;
+
+// How many times a request to Ollama is tried before giving up (see send_to_ollama())
+const OLLAMA_ATTEMPTS: u32 = 3;
+
+// Ollama model used when neither --model nor DOIT_MODEL is given
+const DEFAULT_MODEL: &str = "llama3.2";
+
+// The question `doit summarize` sends to the AI
+const SUMMARIZE_PROMPT: &str = "Summarize these tasks, group related ones, and suggest the top 3 to tackle.";
+
+// Longest task description we create from an AI answer (--save-as-task). Longer answers are cut
+const MAX_SAVED_ANSWER_CHARS: usize = 200;
+
 /// Simple TODO list 
 #[derive(Parser, Debug)] // Ask clap to automatically implement the Parser trait for this struct
 #[command(version, about = "A tiny CLI todo app")] // The #[command()] attribute is provided by clap to configure the CLI: adds --version flag and sets the description
 struct CLI {
     #[command(subcommand)] // Tell clap this field will hold which subcommand the user chose
     command: Commands, // This field stores the subcommand the user chose. Type is 'Commands' (an enum defined below)
+
+    /// On error, print a JSON object {"error": "...", "kind": "..."} to stderr instead of a plain message
+    #[arg(long, global = true)] // global = true allows the flag before or after the subcommand
+    error_json: bool,
+
+    /// When to use colors: auto (only in a terminal, and not if NO_COLOR is set), always or never
+    #[arg(long, global = true, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Before saving, drop pending tasks whose description exactly matches another pending task (the lowest ID is kept)
+    #[arg(long, global = true, env = "DOIT_DEDUPE_ON_SAVE")] // Off by default. DOIT_DEDUPE_ON_SAVE=true turns it on for every command
+    dedupe_on_save: bool,
+
+    /// Print how long loading/saving tasks and (for ask) the AI answer took, to stderr at exit
+    #[arg(long, global = true)]
+    profile: bool,
+}
+
+// Possible values of --color
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ColorChoice {
+    Auto, // Colors only when printing to a terminal and NO_COLOR isn't set
+    Always, // Always print colors, even into a pipe or file
+    Never, // Never print colors
+}
+
+// Whether colored output is on. It's decided once in main() from --color and read by every renderer.
+// OnceLock is a global that can be set only once - perfect for a setting that never changes while the program runs
+static COLOR_ENABLED: OnceLock<bool> = OnceLock::new();
+
+// Whether save_tasks() removes duplicate pending tasks (--dedupe-on-save). Also set once in main()
+static DEDUPE_ON_SAVE: OnceLock<bool> = OnceLock::new();
+
+// Time spent in the slow parts of a command, collected for --profile
+#[derive(Default)]
+struct Profile {
+    load: Duration, // Reading (and parsing) the tasks file, added up over every load
+    save: Duration, // Writing the tasks file, added up over every save
+    first_token: Option<Duration>, // From sending the AI request until the first text arrived (first answer only)
+    streaming: Duration, // From sending each AI request until its answer was complete, added up
+}
+
+// The collected timings. None means --profile is off and nothing is recorded.
+// A Mutex makes it safe to change a global from async code, which may run on different threads
+static PROFILE: std::sync::Mutex<Option<Profile>> = std::sync::Mutex::new(None);
+
+// Update the timings, but only when --profile is on
+// `impl FnOnce(&mut Profile)` accepts any closure that changes a Profile, like |p| p.load += elapsed
+fn record_profile(update: impl FnOnce(&mut Profile)) {
+    // lock() only fails if another thread panicked while holding the lock - then we just skip recording
+    if let Ok(mut profile) = PROFILE.lock()
+        && let Some(profile) = profile.as_mut()
+    {
+        update(profile);
+    }
+}
+
+// Print the --profile report to stderr (if --profile is on)
+fn print_profile() {
+    if let Ok(profile) = PROFILE.lock()
+        && let Some(profile) = profile.as_ref()
+    {
+        // {:?} prints a Duration in a readable unit, e.g. 1.234ms or 2.5s
+        eprintln!("profile: load {:?}, save {:?}", profile.load, profile.save);
+        if let Some(first_token) = profile.first_token {
+            eprintln!("profile: first token {:?}, streaming {:?}", first_token, profile.streaming);
+        }
+    }
 }
 
 #[derive(Subcommand, Debug)] // Ask clap to automatically implement the Subcommand trait for this enum
 enum Commands { // Each variant represents a different subcommand the user can run
     /// Show the whole todo list
-    List,
+    List {
+        /// Collapse completed tasks into a single summary line
+        // `env` lets users turn this on by default with DOIT_FOLD_COMPLETED=true
+        #[arg(long, env = "DOIT_FOLD_COMPLETED")]
+        fold_completed: bool,
+
+        /// Show completed tasks even when folding is turned on (wins over --fold-completed and DOIT_FOLD_COMPLETED)
+        // No conflicts_with here: clap would also count DOIT_FOLD_COMPLETED as a conflict, and that's exactly what this overrides
+        #[arg(long)]
+        completed: bool,
+
+        /// Print only task IDs (or descriptions) separated by NUL bytes, for `xargs -0`
+        // `--print0` alone prints IDs, `--print0 description` prints descriptions
+        #[arg(long, value_name = "FIELD", num_args = 0..=1, default_missing_value = "id", value_parser = ["id", "description"])]
+        print0: Option<String>,
+
+        /// Show the tasks as a table with aligned columns
+        #[arg(long)]
+        table: bool,
+
+        /// Print the whole task list as JSON, for scripts (e.g. `doit list --json | jq`)
+        #[arg(long, conflicts_with_all = ["print0", "table"])] // Only one output format at a time
+        json: bool,
+
+        /// Only show tasks with this tag
+        #[arg(long, value_name = "TAG", value_parser = parse_tag)]
+        tag: Option<String>,
+
+        /// Only show pending tasks without a due date (to find tasks that still need scheduling)
+        #[arg(long)]
+        no_due: bool,
+
+        /// Show one screenful at a time: Enter shows the next page, q quits (only in a terminal)
+        #[arg(long, conflicts_with_all = ["print0", "json", "table"])] // Pages are for the normal list only
+        paginate: bool,
+
+        /// Show how long ago each pending task was added
+        #[arg(long, conflicts_with_all = ["print0", "json", "table"])]
+        age: bool,
+
+        /// Show only completed tasks, grouped by the day they were finished (most recent first)
+        #[arg(long, conflicts_with_all = ["print0", "json", "table", "paginate", "age", "no_due"])]
+        by_completion_date: bool,
+    },
+
+    /// Find tasks whose description contains some text (case-insensitive)
+    Search {
+        /// Text to look for
+        #[arg(value_name = "QUERY")]
+        query: String,
+    },
+
+    /// Show the most recently added or completed tasks, newest first
+    Recent {
+        /// How many tasks to show
+        #[arg(long, value_name = "N", default_value_t = 10)]
+        limit: usize,
+    },
 
     /// Add a new task
     Add {
         /// Text of the new task
         #[arg(value_name = "TASK")] // Customize how this argument appears in --help text
         task: String,
+
+        /// Rough effort estimate in minutes
+        #[arg(long, value_name = "MINUTES")]
+        estimate: Option<u32>, // Option because the flag is optional: None if the user didn't pass it
+
+        /// Link to a related webpage, issue or document
+        #[arg(long, value_name = "URL", value_parser = parse_url)] // parse_url() rejects values that don't look like a URL
+        url: Option<String>,
+
+        /// Due date in the form YYYY-MM-DD
+        #[arg(long, value_name = "DATE", value_parser = parse_due)] // parse_due() rejects dates like 2025-13-40
+        due: Option<String>,
+
+        /// How important the task is (default: medium). A "!high" word in the task text wins over this
+        // value_enum lets clap accept only low, medium or high
+        // `env`: DOIT_DEFAULT_PRIORITY is used when --priority isn't given
+        #[arg(long, value_enum, env = "DOIT_DEFAULT_PRIORITY")]
+        priority: Option<Priority>,
+
+        /// Label for the task, like work or home (repeat for more tags: --tag work --tag urgent)
+        #[arg(long, value_name = "TAG", value_parser = parse_tag)] // A Vec collects every --tag given
+        tag: Vec<String>,
+    },
+
+    /// Open a task's URL in the default browser
+    Open {
+        /// ID of the task to open
+        #[arg(value_name = "ID")]
+        id: u32,
+    },
+
+    /// Show the one task to work on next: highest priority, then earliest due date, then lowest ID
+    Next {
+        /// Mark that task as done right away
+        #[arg(long)]
+        done: bool,
     },
 
+    /// Show how many tasks are done and pending, with a progress bar
+    Stats,
+
     /// Mark a task as completed
     Done {
-        /// ID of the task to mark done
-        #[arg(value_name = "ID")] // Customize how this argument appears in --help text
-        id: u8,
+        /// ID(s) of the task(s) to mark done, e.g. `doit done 1 3 7`
+        #[arg(value_name = "ID", num_args = 1.., required = true)] // num_args = 1.. accepts one or more IDs
+        ids: Vec<u32>,
+
+        /// Stop at the first ID that isn't found (tasks marked before it are still saved)
+        #[arg(long)]
+        fail_fast: bool,
+    },
+
+    /// Mark a completed task as not done again
+    Undone {
+        /// ID of the task to reopen
+        #[arg(value_name = "ID")]
+        id: u32,
     },
 
     /// Delete a task
     Remove {
         /// ID of the task to delete
         #[arg(value_name = "ID")] // Customize how this argument appears in --help text
-        id: u8,
+        id: u32,
+    },
+
+    /// Move a task among the tasks with the same priority (`list` always shows higher priorities first)
+    Move {
+        /// ID of the task to move
+        id: u32,
+
+        /// New position, starting at 1 (too big means the end). `list` still puts higher-priority tasks above it
+        position: usize,
+    },
+
+    /// Give the tasks sequential IDs (1, 2, 3, ...) again after removals left gaps
+    Renumber,
+
+    /// Delete all completed tasks
+    Clear {
+        /// Delete every task, not only the completed ones (asks for confirmation first)
+        #[arg(long)]
+        all: bool,
+    },
+
+    /// Apply task operations read from stdin, one JSON object per line
+    Apply {
+        /// Run every line even if some fail (the default)
+        #[arg(long, conflicts_with = "fail_fast")]
+        keep_going: bool,
+
+        /// Stop at the first line that fails (changes made before it are still saved)
+        #[arg(long)]
+        fail_fast: bool,
+    },
+
+    /// Serve JSON-RPC requests on stdin/stdout, one JSON object per line (for editor plugins)
+    Rpc,
+
+    /// Add every non-empty line of a text file as a new task
+    Import {
+        /// The text file to read, one task per line
+        #[arg(value_name = "FILE")]
+        path: String,
+    },
+
+    /// Print the task list in another format (to stdout)
+    Export {
+        /// Output format
+        #[arg(long, value_enum)]
+        format: ExportFormat,
+    },
+
+    /// Compare two task files and show added, removed and changed tasks
+    Diff {
+        /// The older task file
+        #[arg(value_name = "FILE_A")]
+        file_a: String,
+
+        /// The newer task file
+        #[arg(value_name = "FILE_B")]
+        file_b: String,
+    },
+
+    /// Save the task list as a reusable template, or add a template's tasks
+    Template {
+        #[command(subcommand)] // The template command has its own subcommands: save, apply, list
+        action: TemplateAction,
+    },
+
+    /// Show every tag with the number of tasks that use it, or rename a tag
+    Tags {
+        /// Rename tag OLD to NEW on every task, e.g. `doit tags --rename wrk work`
+        #[arg(long, num_args = 2, value_names = ["OLD", "NEW"], value_parser = parse_tag)]
+        rename: Option<Vec<String>>,
+    },
+
+    /// Manage the config file
+    Config {
+        #[command(subcommand)] // Subcommands: init
+        action: ConfigAction,
+    },
+
+    /// Let the AI rewrite task descriptions to be concise and consistent
+    Reword {
+        /// ID of the task to reword
+        #[arg(value_name = "ID", required_unless_present = "all")] // Either an ID or --all must be given
+        id: Option<u32>,
+
+        /// Reword every pending task
+        #[arg(long, conflicts_with = "id")] // Can't use an ID and --all together
+        all: bool,
+
+        /// Only show the proposed rewrites, don't change anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// How many requests to send to the AI at the same time
+        #[arg(long, value_name = "N", default_value_t = 2)]
+        concurrency: usize,
+
+        /// Ollama model to use
+        #[arg(long, env = "DOIT_MODEL", default_value = DEFAULT_MODEL)]
+        model: String,
     },
 
     /// Ask AI
     Ask {
         /// AI prompt - one question (no conversation history)
-        #[arg(value_name = "PROMPT")] // Customize how this argument appears in --help text
-        prompt: String,
+        // Option because `doit ask --reset` doesn't need a prompt
+        #[arg(value_name = "PROMPT", required_unless_present_any = ["reset", "template"])] // Customize how this argument appears in --help text
+        prompt: Option<String>,
 
         /// Start a chat session with AI 
-        #[arg(short, long)]  // Allows this flag to be used as either -c (short) or --chat (long)
+        // Allows this flag to be used as either -c (short) or --chat (long)
+        // conflicts_with_all: these options only work for a single question, so clap rejects them together with --chat
+        #[arg(short, long, conflicts_with_all = ["save_as_task", "retry_on_empty", "apply"])]
         chat: bool,
+
+        /// Continue the last chat session with a follow-up question
+        // `continue` is a Rust keyword, so the field has another name and we set the flag name by hand
+        #[arg(long = "continue", conflicts_with_all = ["save_as_task", "retry_on_empty", "apply"])]
+        continue_chat: bool,
+
+        /// Chat using a session file: continue the conversation saved in FILE (if it exists) and save it there on exit
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["continue_chat", "save_as_task", "retry_on_empty", "apply"])]
+        session: Option<PathBuf>,
+
+        /// Forget the saved chat session
+        #[arg(long, conflicts_with_all = ["chat", "continue_chat", "session"])]
+        reset: bool,
+
+        /// Ask with a saved prompt. {tasks} and {question} in it are replaced by your tasks and PROMPT
+        #[arg(long, value_name = "NAME", conflicts_with_all = ["chat", "continue_chat", "session", "reset"])] // One-shot only
+        template: Option<String>,
+
+        /// Save PROMPT as a reusable prompt template called NAME (nothing is sent to the AI)
+        #[arg(long, value_name = "NAME", requires = "prompt", conflicts_with_all = ["chat", "continue_chat", "session", "reset", "template"])]
+        save_template: Option<String>,
+
+        // All the other options are shared with `summarize`, see AskArgs below
+        #[command(flatten)]
+        args: AskArgs,
+    },
+
+    /// Ask AI for a quick overview of your tasks and the top 3 to tackle
+    Summarize {
+        // Accepts the same options as `ask` (--persona, --timeout, ...)
+        #[command(flatten)]
+        args: AskArgs,
+    },
+
+    /// Check crates.io for a newer version and install it
+    // #[cfg(...)] removes this variant completely unless the program is built with `--features self-update`
+    #[cfg(feature = "self-update")]
+    SelfUpdate {
+        /// Install the update without asking for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+// Options shared by every command that talks to the AI (`ask` and `summarize`)
+// #[derive(Args)] lets clap read these fields as flags, and #[command(flatten)] adds them to a subcommand
+#[derive(Args, Debug)]
+struct AskArgs {
+    /// Ollama model to use
+    // `env` makes DOIT_MODEL the fallback when --model isn't given
+    #[arg(long, env = "DOIT_MODEL", default_value = DEFAULT_MODEL)]
+    model: String,
+
+    /// Print each streamed token as a JSON line ({"token":"...","done":false}) instead of plain text
+    #[arg(long)] // Only a long form: --ndjson-out
+    ndjson_out: bool,
+
+    /// Chat mode: send the task list as its own message (role "user" or "tool") instead of inside the system prompt
+    // num_args = 0..=1 makes the value optional: `--context-as-message` alone means "user"
+    #[arg(long, value_name = "ROLE", num_args = 0..=1, default_missing_value = "user", value_parser = ["user", "tool"])]
+    context_as_message: Option<String>,
+
+    /// Give up if the whole request (including the streamed answer) takes longer than this many seconds
+    #[arg(long, value_name = "SECONDS")] // No default: wait as long as the model needs
+    timeout: Option<u64>,
+
+    /// Give up if connecting to Ollama takes longer than this many seconds
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_CONNECT_TIMEOUT)] // Lower it to fail fast when Ollama runs on a remote host
+    connect_timeout: u64,
+
+    /// Replace task descriptions with placeholders (Task A, Task B, ...) before sending them to the AI
+    #[arg(long)]
+    anonymize: bool,
+
+    /// Give the assistant a name
+    #[arg(long, value_name = "NAME")]
+    assistant_name: Option<String>,
+
+    /// Tone of the assistant's answers
+    #[arg(long, value_enum)] // value_enum lets clap accept only the variants of Persona (terse, friendly, coach)
+    persona: Option<Persona>,
+
+    /// Collapse runs of 3 or more newlines in the answer into a single blank line
+    #[arg(long)]
+    trim_blank_lines: bool,
+
+    /// Save the AI's answer as a new task (one-shot mode only)
+    #[arg(long)]
+    save_as_task: bool,
+
+    /// Let the AI add tasks: {"action":"add","task":"..."} blocks in its answer are saved as new tasks (one-shot mode only)
+    #[arg(long)]
+    apply: bool,
+
+    /// With --apply: add the AI's tasks without asking about each one (for scripts)
+    #[arg(short, long, requires = "apply")]
+    yes: bool,
+
+    /// Ask again if the model finishes without any text (one-shot mode only)
+    #[arg(long)]
+    retry_on_empty: bool,
+
+    /// How many times --retry-on-empty asks again
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "retry_on_empty")] // Only makes sense together with --retry-on-empty
+    retries: u32,
+
+    /// Show a blinking block cursor after the text while the answer is still streaming
+    #[arg(long)] // Ignored when colors are off (--color never, NO_COLOR or output that isn't a terminal)
+    show_streaming_cursor: bool,
+
+    /// Only send tasks with at least this priority to the AI (default: all tasks)
+    #[arg(long, value_enum, value_name = "PRIORITY")]
+    min_priority: Option<Priority>,
+
+    /// Chat mode: if the connection drops mid-answer, keep the partial answer so you can type "continue"
+    #[arg(long)]
+    resume_partial: bool,
+
+    /// Wait for the complete answer and print it at once instead of streaming it (handy for scripts)
+    #[arg(long)]
+    no_stream: bool,
+
+    /// Remove ANSI escape codes (colors, cursor moves) from the answer. Always on when the output isn't a terminal
+    #[arg(long)]
+    strip_ansi: bool,
+
+    /// Print the size of the context and prompt sent to the AI (to stderr) before sending it
+    #[arg(long)]
+    show_context_size: bool,
+
+    /// Also send completed tasks to the AI (by default only pending tasks are sent)
+    #[arg(long, env = "DOIT_INCLUDE_COMPLETED")] // DOIT_INCLUDE_COMPLETED=true makes it the default
+    include_completed: bool,
+
+    /// Count the tokens of the answer on stderr (live when the answer goes to a file or pipe)
+    #[arg(long)] // Ignored when stderr isn't a terminal
+    progress: bool,
+}
+
+// Tone presets for the AI assistant (--persona)
+// ValueEnum lets clap turn "terse"/"friendly"/"coach" on the command line into these variants
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Persona {
+    Terse, // Short, to-the-point answers
+    Friendly, // Warm and encouraging
+    Coach, // Keeps you focused and motivated
+}
+
+// How important a task is
+// ValueEnum: clap accepts "low"/"medium"/"high" on the command line
+// rename_all = "lowercase": stored as "low"/"medium"/"high" in tasks.json
+// PartialOrd/Ord: the order of the variants is the order of importance (Low < Medium < High), so we can sort by it
+// Default + #[default]: tasks saved before priorities existed get Medium
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+enum Priority {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl Priority {
+    // The small colored circle shown in the list
+    fn emoji(self) -> &'static str {
+        match self {
+            Priority::High => "🔴",
+            Priority::Medium => "🟡",
+            Priority::Low => "🟢",
+        }
+    }
+}
+
+// Formats `doit export` can write
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ExportFormat {
+    Org, // Emacs org-mode TODO headlines
+    Markdown, // A checklist for GitHub issues or notes
+}
+
+// Subcommands of `doit template`
+#[derive(Subcommand, Debug)]
+enum TemplateAction {
+    /// Save the current task list as a template
+    Save {
+        /// Template name (letters, numbers, '-' and '_')
+        #[arg(value_name = "NAME")]
+        name: String,
+    },
+
+    /// Add the tasks of a template to the current list
+    Apply {
+        /// Template name
+        #[arg(value_name = "NAME")]
+        name: String,
+    },
+
+    /// Show all saved templates
+    List,
+}
+
+// Subcommands of `doit config`
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Write a commented config file with every setting and its default
+    Init {
+        /// Overwrite the config file if it already exists
+        #[arg(long)]
+        force: bool,
     },
 }
 
 // #[derive(Serialize, Deserialize)] Tell serde to automatically implement these traits for our custom type.
 // This allows us to convert Task to JSON (serialize) and JSON to Task (deserialize).
-#[derive(Serialize, Deserialize)] 
+// Clone lets us make a copy of a task (used when saving a de-duplicated copy of the list)
+#[derive(Serialize, Deserialize, Clone)] 
 struct Task {
-    id: u8, // The unique identifier for this task
+    id: u32, // The unique identifier for this task
     description: String, // What the task is about
     completed: bool, // Whether the task is done or not
+    // #[serde(default)] uses None when the field is missing, so older tasks.json files still load
+    #[serde(default)]
+    estimate_minutes: Option<u32>, // Rough effort estimate in minutes (None if not estimated)
+    #[serde(default)]
+    url: Option<String>, // Link to a related webpage, issue or doc (None if there is none)
+    #[serde(default)]
+    due: Option<String>, // Due date as YYYY-MM-DD (None if the task has no due date)
+    #[serde(default)] // Missing in older files: Priority::default(), which is Medium
+    priority: Priority, // How important the task is
+    #[serde(default)] // Missing in older files: an empty list
+    tags: Vec<String>, // Labels like "work" or "home"
+    #[serde(default)] // Missing in older files: an empty string (we don't know when they were created)
+    created_at: String, // When the task was added, as an RFC 3339 timestamp like 2025-03-31T09:15:00+02:00
+    #[serde(default)]
+    completed_at: Option<String>, // When the task was marked done (None while it's pending)
 }
  
 // Request structure for Ollama's /api/generate endpoint
@@ -74,6 +596,12 @@ struct GenerateRequest {
     stream: bool, // Whether to stream the response word-by-word (true) or wait for complete response (false)
 }
 
+// Body of an error response from Ollama, e.g. {"error": "model 'nonexistent' not found"}
+#[derive(Deserialize)]
+struct OllamaErrorResponse {
+    error: String, // Ollama's explanation of what went wrong
+}
+
 // Response structure for /api/generate endpoint
 // Deserialize trait allows converting JSON response back to this struct
 #[derive(Deserialize)]
@@ -100,6 +628,13 @@ struct Message {
     content: String, // The actual message text
 }
 
+// A chat conversation saved to disk, so `doit ask --continue` can pick it up later
+#[derive(Serialize, Deserialize)]
+struct ChatSession {
+    context_len: usize, // How many messages at the start hold the task context (replaced by /refresh)
+    messages: Vec<Message>, // The whole conversation, including the context messages
+}
+
 // Response structure for /api/chat
 // Deserialize trait allows converting JSON response back to this struct
 #[derive(Deserialize)]
@@ -108,121 +643,1799 @@ struct ChatResponse {
     done: bool, // Whether this is the final chunk (true = streaming complete)
 }
 
-// Load tasks from the JSON file
-fn load_tasks() -> Vec<Task> { // Returns a vector containing Task objects
-    if Path::new(TASKS_FILE).exists() { // Path::new(TASKS_FILE) creates a Path object. exists() checks if the file actually exists. 
-        let data = fs::read_to_string(TASKS_FILE) // Read file contents into a String. Returns Result<String, Error>
-            .unwrap(); // Extract the String from Result (panics if error)
-        serde_json::from_str(&data) // Use serde_json's from_str() to deserialize the JSON string into Vec<Task>. Returns Result<Vec<Task>, Error>
-            .unwrap_or(Vec::new()) // Extract the vector, or return empty vector if deserialization fails
+// One operation read by `doit apply`, for example {"op":"done","id":3}
+// #[serde(tag = "op")] tells serde that the "op" field decides which variant to build,
+// and rename_all = "lowercase" lets the JSON say "add" instead of "Add"
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Operation {
+    Add {
+        description: String, // Text of the new task
+        #[serde(default)]
+        estimate_minutes: Option<u32>, // Optional estimate, like --estimate on `add`
+    },
+    Done { id: u32 }, // Mark a task as completed
+    Remove { id: u32 }, // Delete a task
+}
+
+// One request read by `doit rpc`, for example {"jsonrpc":"2.0","id":1,"method":"done","params":{"id":3}}
+#[derive(Deserialize)]
+struct RpcRequest {
+    // The id can be a number or a string (or missing), so we keep it as a generic JSON value and send it back unchanged
+    #[serde(default)]
+    id: serde_json::Value,
+    method: String, // "list", "add", "done" or "remove"
+    #[serde(default)]
+    params: serde_json::Value, // Arguments of the method, like {"id":3}. Missing for "list"
+}
+
+// Response structure for crates.io's /api/v1/crates/<name> endpoint
+// We only need the newest published version, so all the other fields are ignored
+#[cfg(feature = "self-update")]
+#[derive(Deserialize)]
+struct CratesIoResponse {
+    #[serde(rename = "crate")] // `crate` is a Rust keyword, so we name the field `krate` and map it to "crate"
+    krate: CratesIoCrate,
+}
+
+// The part of the crates.io response describing the crate itself
+#[cfg(feature = "self-update")]
+#[derive(Deserialize)]
+struct CratesIoCrate {
+    max_version: String, // The latest published version, e.g. "0.2.0"
+}
+
+// Options that change how the AI answer is requested and printed
+// We group them in one struct so we don't have to pass a long list of parameters
+// through ask_ai -> ask_chat/ask_once -> send_chat_message
+struct AskOptions {
+    client: reqwest::Client, // HTTP client (with the chosen timeouts) shared by every request
+    model: String, // Name of the Ollama model that answers (e.g. "llama3.2" or "mistral")
+    ndjson_out: bool, // Re-emit every token as a JSON object on its own line
+    context_as_message: Option<String>, // Some(role) to send the tasks as a separate chat message with that role
+    anonymize: bool, // Hide task descriptions behind placeholders like "Task A"
+    assistant_name: Option<String>, // Name the assistant introduces itself with
+    persona: Option<Persona>, // Tone of the answers
+    trim_blank_lines: bool, // Squeeze long runs of empty lines from chatty models
+    save_as_task: bool, // Store the complete one-shot answer as a new task
+    apply_actions: bool, // Carry out the {"action": ...} blocks in the one-shot answer (--apply)
+    apply_without_asking: bool, // --yes: don't confirm each action
+    retries_on_empty: u32, // How many times to repeat a one-shot question that got an empty answer (0 = never)
+    streaming_cursor: bool, // Draw a blinking block after the latest token until the answer is complete
+    min_priority: Option<Priority>, // Leave tasks below this priority out of the AI context (None = send all)
+    include_completed: bool, // Send completed tasks to the AI too
+    show_context_size: bool, // Report how big each request is before sending it
+    prompt_template: Option<String>, // Saved prompt from `ask --template`, with {tasks}/{question} placeholders
+    system_prompt: Option<String>, // Who the AI should be, from system_prompt in the config file
+    no_stream: bool, // Ask for the whole answer in one response instead of a stream of chunks
+    strip_ansi: bool, // Drop ANSI escape codes the model writes, so files don't fill up with garbage
+    // Where we are inside an escape code. Like newline_run, a code can be split over two chunks
+    ansi_state: std::cell::Cell<AnsiState>,
+    resume_partial: bool, // Keep a half-streamed chat answer instead of failing when the connection drops
+    progress: bool, // Show a token counter on stderr
+    token_count: std::cell::Cell<usize>, // Tokens received for the current answer (for --progress)
+    // How many newlines in a row we've printed so far. A model can split "\n\n\n" over several
+    // chunks, so we must remember this between calls to print_token().
+    // Cell lets us change the number through a shared reference (&AskOptions) - it's safe because
+    // we only ever read or replace the whole value
+    newline_run: std::cell::Cell<usize>,
+}
+
+// Load tasks from the JSON file
+// Returns the tasks, or an error if the file exists but can't be read (e.g. no permission)
+fn load_tasks() -> Result<Vec<Task>, Box<dyn std::error::Error>> {
+    load_tasks_from(&tasks_file_path())
+}
+
+// Load tasks from any JSON file (used by load_tasks(), `doit diff` and templates)
+// `path: &Path` is a borrowed file path
+fn load_tasks_from(path: &Path) -> Result<Vec<Task>, Box<dyn std::error::Error>> {
+    let start = Instant::now(); // Start the clock for --profile
+    let tasks = if path.exists() { // exists() checks if the file actually exists. 
+        let data = fs::read_to_string(path) // Read file contents into a String. Returns Result<String, Error>
+            // map_err() adds the path to the error, so the message says which file failed
+            .map_err(|e| io_error_with_path(e, "read", path))?;
+        // Deserialize the file contents (JSON array or NDJSON) into Vec<Task>. Returns Result<Vec<Task>, Error>
+        // A broken file is an error, not an empty list: with an empty list the next save would
+        // overwrite the file with [] and whatever could still be rescued from it would be gone
+        parse_tasks(&data).map_err(|e| DoitError::InvalidFile(format!(
+            "{} is corrupt and was left untouched ({}). Fix it or move it away to start a new list",
+            path.display(), e
+        )))?
+    } else {
+        Vec::new() // Return an empty vector if file doesn't exist
+    };
+    record_profile(|p| p.load += start.elapsed()); // elapsed() is the time since `start`
+    Ok(tasks)
+}
+
+// Turn the contents of a tasks file into Vec<Task>
+// Two formats are accepted, so a file round-trips whichever DOIT_STORAGE_FORMAT wrote it:
+//   - a JSON array:  [ {"id":1,...}, {"id":2,...} ]   (the default "pretty" format)
+//   - NDJSON:        one JSON object per line          (the "ndjson" format)
+fn parse_tasks(data: &str) -> serde_json::Result<Vec<Task>> {
+    let trimmed = data.trim_start();
+    if trimmed.is_empty() {
+        Ok(Vec::new()) // An empty file is an empty list, not a broken one
+    } else if trimmed.starts_with('[') {
+        // Use serde_json's from_str() to deserialize the JSON string into Vec<Task>
+        serde_json::from_str(data)
+    } else {
+        data.lines()
+            .filter(|line| !line.trim().is_empty()) // Skip empty lines
+            .map(serde_json::from_str::<Task>) // Parse each line into a Task
+            // Collecting an iterator of Result<Task> into Result<Vec<Task>> stops at the first error
+            .collect()
+    }
+}
+
+// How the tasks file is written, chosen with the DOIT_STORAGE_FORMAT environment variable
+enum StorageFormat {
+    Pretty, // One indented JSON array (default, same as older versions)
+    Ndjson, // One compact JSON object per line - long descriptions stay on one line, which is nicer to diff in git
+}
+
+// Read DOIT_STORAGE_FORMAT ("pretty" or "ndjson"). Missing means pretty
+fn storage_format() -> StorageFormat {
+    // as_deref() turns Result<String, _> into Result<&str, _> so we can match on string literals
+    match std::env::var("DOIT_STORAGE_FORMAT").as_deref() {
+        Ok("ndjson") => StorageFormat::Ndjson,
+        Ok("pretty") | Err(_) => StorageFormat::Pretty,
+        Ok(other) => {
+            eprintln!("Warning: unknown DOIT_STORAGE_FORMAT '{}', using pretty", other);
+            StorageFormat::Pretty
+        },
+    }
+}
+
+// Save tasks to the JSON file
+// Takes a reference (&Vec<Task>) instead of taking ownership (Vec<Task>).
+// Why use a reference?
+// 1. Efficiency - we don't need to move or copy the entire vector into this function
+// 2. We only need to READ the tasks to convert them to JSON, not modify or take ownership
+// 3. After calling save_tasks(&tasks), the caller can still use 'tasks' because we just borrowed it
+fn save_tasks(tasks: &Vec<Task>) -> Result<(), Box<dyn std::error::Error>> {
+    let path = tasks_file_path();
+    // The first save may go to a folder that doesn't exist yet (e.g. ~/.local/share/doit)
+    // create_dir_all() creates it and all missing parents, and does nothing if it's already there
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| io_error_with_path(e, "create", parent))?;
+    }
+    // Every command saves through here, so --dedupe-on-save cleans up after all of them
+    if DEDUPE_ON_SAVE.get().copied().unwrap_or(false) {
+        let deduped = without_duplicates(tasks);
+        if deduped.len() < tasks.len() {
+            // Tell the user which IDs disappeared, so nothing vanishes silently
+            let removed: Vec<String> = tasks.iter()
+                .filter(|t| !deduped.iter().any(|kept| kept.id == t.id))
+                .map(|t| format!("#{}", t.id))
+                .collect();
+            eprintln!("Note: removed duplicate task(s) {}", removed.join(", "));
+            return save_tasks_to(&path, &deduped);
+        }
+    }
+    save_tasks_to(&path, tasks)
+}
+
+// Copy the list without duplicate pending tasks
+// A pending task is a duplicate if another pending task has exactly the same description and a lower ID.
+// Completed tasks are always kept - doing the same thing twice is fine, it's history
+fn without_duplicates(tasks: &[Task]) -> Vec<Task> {
+    tasks.iter()
+        .filter(|task| {
+            task.completed || !tasks.iter().any(|other| {
+                !other.completed && other.description == task.description && other.id < task.id
+            })
+        })
+        .cloned() // filter() gives references (&Task). cloned() turns them into owned copies
+        .collect()
+}
+
+// Save tasks to any JSON file (used by save_tasks() and by templates)
+fn save_tasks_to(path: &Path, tasks: &Vec<Task>) -> Result<(), Box<dyn std::error::Error>> {
+    let start = Instant::now(); // Start the clock for --profile
+    let json = match storage_format() {
+        StorageFormat::Pretty => serde_json::to_string_pretty(tasks)?, // Serialize the vector to pretty-formatted JSON string. ? returns early on error
+        StorageFormat::Ndjson => tasks.iter()
+            .map(|task| serde_json::to_string(task).map(|line| line + "\n")) // Compact JSON (one line) for each task
+            // Collecting Results into Result<String, _> stops at the first error, or glues all the lines together
+            .collect::<Result<String, _>>()?,
+    };
+    // If doit is killed halfway through, the tasks file still holds the old complete list
+    write_atomically(path, json.as_bytes())?;
+    record_profile(|p| p.save += start.elapsed());
+    // The () or Ok(()) means "unit type" - means the function succeeded but has nothing to return (like void in other languages)
+    Ok(())
+}
+
+// Write a file so that it's never half written: first to a temporary file next to the real one,
+// then rename it over the real one. A rename on the same filesystem is atomic, so anyone
+// reading the file sees either the old or the new complete content
+fn write_atomically(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let temp_path = path.with_file_name(format!(".{}.tmp", file_name)); // e.g. .tasks.json.tmp (hidden on Linux/macOS)
+    write_synced(&temp_path, bytes)
+        .map_err(|e| io_error_with_path(e, "write", &temp_path))?; // If Err, return a message that names the file
+    if let Err(e) = fs::rename(&temp_path, path) {
+        let _ = fs::remove_file(&temp_path); // Don't leave the temporary file behind (nothing to do if this fails too)
+        return Err(io_error_with_path(e, "write", path));
+    }
+    Ok(())
+}
+
+// Add the file name to an IO error, e.g. "can't write /home/you/tasks.json: Permission denied"
+// We build a new io::Error with the same kind instead of a String, so the error stays an IO error
+// (--error-json reports it as "io", and the kind still says what went wrong, like PermissionDenied)
+fn io_error_with_path(error: io::Error, action: &str, path: &Path) -> io::Error {
+    io::Error::new(error.kind(), format!("can't {} {}: {}", action, path.display(), error))
+}
+
+// Write bytes to a file and wait until they are really on the disk
+// sync_all() makes sure the data is stored before we rename the file, otherwise a crash
+// right after the rename could still leave an empty file
+fn write_synced(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+// Settings from the config file (config.toml)
+// Every field is optional, and a missing file means "all defaults"
+#[derive(Deserialize, Default)]
+#[serde(default)] // Fields missing from the file get their Default value
+struct Config {
+    system_prompt: Option<String>, // Replaces "You are a helpful assistant." at the start of every AI prompt
+    lowercase_tags: Option<bool>, // Store tags in lowercase, so "Work" and "work" are the same tag (default: true)
+    max_completed: Option<usize>, // Keep at most this many completed tasks, `done` removes the oldest (default: keep all)
+    default_priority: Option<Priority>, // Priority for new tasks, written like in tasks.json: "low", "medium" or "high"
+}
+
+impl Config {
+    // Turn a tag into the form we store and compare: lowercase unless lowercase_tags = false
+    // (parse_tag() has already trimmed it)
+    fn normalize_tag(&self, tag: &str) -> String {
+        if self.lowercase_tags.unwrap_or(true) {
+            tag.to_lowercase()
+        } else {
+            tag.to_string()
+        }
+    }
+}
+
+// What `doit config init` writes: every setting, commented out, with its default value.
+// Keep this in sync with the Config struct when adding a setting
+const SAMPLE_CONFIG: &str = r#"# doit configuration
+# Remove the '#' in front of a setting to change it. Missing settings use the default shown.
+
+# Replaces "You are a helpful assistant." at the start of every AI prompt.
+# Your tasks are still sent after it. --persona and --assistant-name win over it.
+# system_prompt = "You are a terse project manager. Answer in bullet points."
+
+# Store tags in lowercase, so "Work" and "work" are the same tag.
+# lowercase_tags = true
+
+# Priority for new tasks: "low", "medium" or "high".
+# DOIT_DEFAULT_PRIORITY, --priority and "!high" in the task text all win over it.
+# default_priority = "medium"
+
+# Keep only the newest completed tasks. After `doit done`, the oldest ones beyond
+# this count are removed from the list.
+# max_completed = 50
+"#;
+
+// Where the config file lives, e.g. ~/.config/doit/config.toml on Linux
+// DOIT_CONFIG points to another file (handy for trying out settings)
+fn config_path() -> PathBuf {
+    match std::env::var_os("DOIT_CONFIG") {
+        Some(path) if !path.is_empty() => PathBuf::from(path),
+        _ => dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("doit")
+            .join("config.toml"),
+    }
+}
+
+// Read the config file. No file is fine (defaults), but a broken one is an error:
+// silently ignoring it would leave the user wondering why their settings do nothing
+fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
+    let path = config_path();
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+    let data = fs::read_to_string(&path).map_err(|e| io_error_with_path(e, "read", &path))?;
+    let config = toml::from_str(&data).map_err(|e| DoitError::InvalidFile(format!("invalid config file {}: {}", path.display(), e)))?;
+    Ok(config)
+}
+
+// Folder where doit keeps its own files (like templates)
+// dirs::data_dir() is the standard place for app data on each OS:
+//   Linux: ~/.local/share   macOS: ~/Library/Application Support   Windows: AppData\Roaming
+// If it can't be found (very unusual), fall back to the current directory
+fn data_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("doit") // join() adds a path component: ~/.local/share -> ~/.local/share/doit
+}
+
+// Path of the tasks file
+// DOIT_TASKS_FILE wins if it's set (handy for a per-project list), otherwise the list lives in data_dir(),
+// so it's the same list no matter which folder you run doit in
+fn tasks_file_path() -> PathBuf {
+    match std::env::var_os("DOIT_TASKS_FILE") {
+        Some(path) if !path.is_empty() => PathBuf::from(path),
+        _ => data_dir().join(TASKS_FILE),
+    }
+}
+
+// Folder with the saved task templates
+fn templates_dir() -> PathBuf {
+    data_dir().join("templates")
+}
+
+// Path of the file for one template, e.g. ~/.local/share/doit/templates/weekly.json
+// Returns None if the name could escape the templates folder (like "../tasks") or is empty
+fn template_path(name: &str) -> Option<PathBuf> {
+    if valid_template_name(name) {
+        Some(templates_dir().join(format!("{}.json", name)))
+    } else {
+        None
+    }
+}
+
+// The error for a template name that valid_template_name() rejects
+fn invalid_template_name() -> DoitError {
+    DoitError::InvalidInput("template names may only contain letters, numbers, '-' and '_'".to_string())
+}
+
+// Only allow letters, numbers, '-' and '_' so the name is always a plain file name
+fn valid_template_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
+
+// Path of a saved AI prompt (`ask --save-template`), e.g. ~/.local/share/doit/prompts/standup.txt
+// Same name rules as task templates
+fn prompt_template_path(name: &str) -> Option<PathBuf> {
+    if valid_template_name(name) {
+        Some(data_dir().join("prompts").join(format!("{}.txt", name)))
+    } else {
+        None
+    }
+}
+// Calculate the next available ID for a new task
+// Takes a reference (&Vec<Task>) instead of taking ownership (Vec<Task>).
+// Why use a reference?
+// 1. Efficiency - we don't need to move or copy the entire vector into this function
+// 2. We only need to READ the tasks to find the highest ID, not modify them
+// 3. After calling get_next_id(&tasks), the caller can still use 'tasks' because we just borrowed it
+// Returns u32 - the next available ID number (u32 goes up to about 4 billion, so a long-lived list never runs out)
+fn get_next_id(tasks: &Vec<Task>) -> u32 {
+    tasks.iter() // Iterate over tasks
+        .map(|t| t.id) // Extract just the IDs (example [1, 3, 5])
+        .max() // Find the highest ID. Returns Option<u32>: Some(max_id) or None if empty
+        .unwrap_or(0) + 1 // Extract the value from Some, or use 0 if None (no tasks exist) and Add 1 to get the next available ID
+}
+
+// Check a tag given on the command line: surrounding spaces are dropped, and an empty tag is an error
+// (lowercasing happens later, because it depends on the config file)
+fn parse_tag(value: &str) -> Result<String, String> {
+    let tag = value.trim();
+    if tag.is_empty() {
+        Err("tags can't be empty".to_string())
+    } else {
+        Ok(tag.to_string())
+    }
+}
+
+// Check that a --url value looks like a web address
+// clap calls this while parsing the arguments, so a bad value is rejected before anything is saved
+// Returns the URL on success, or an error message that clap shows to the user
+fn parse_url(value: &str) -> Result<String, String> {
+    // strip_prefix() returns the rest of the text if it starts with the prefix, or None
+    let rest = value.strip_prefix("https://").or_else(|| value.strip_prefix("http://"));
+    match rest {
+        // There must be something after the scheme, and URLs never contain spaces
+        Some(rest) if !rest.is_empty() && !rest.contains(char::is_whitespace) => Ok(value.to_string()),
+        _ => Err(format!("'{}' doesn't look like a URL (it should start with http:// or https://)", value)),
+    }
+}
+
+// Status glyph of a pending task with this priority
+// DOIT_PRIORITY_GLYPHS can replace the defaults with three comma-separated glyphs for high, medium and low,
+// e.g. DOIT_PRIORITY_GLYPHS="!!,!,." for terminals without emoji
+fn priority_glyph(priority: Priority) -> String {
+    if let Ok(glyphs) = std::env::var("DOIT_PRIORITY_GLYPHS") {
+        let glyphs: Vec<&str> = glyphs.split(',').map(str::trim).collect();
+        // Only use the setting if it has exactly three glyphs, otherwise fall back to the defaults
+        if let [high, medium, low] = glyphs[..] {
+            let glyph = match priority {
+                Priority::High => high,
+                Priority::Medium => medium,
+                Priority::Low => low,
+            };
+            return glyph.to_string();
+        }
+    }
+    priority.emoji().to_string()
+}
+
+// Find an inline priority like "!high" in a task description and take it out
+// "Pay rent !high" -> ("Pay rent", Some(High)). Without a marker the text is returned unchanged
+fn take_inline_priority(description: &str) -> (String, Option<Priority>) {
+    let mut priority = None;
+    let mut words = Vec::new();
+    for word in description.split_whitespace() {
+        // strip_prefix('!') gives the rest of the word if it starts with '!'
+        // from_str() is the same parser clap uses for --priority (true = ignore upper/lower case)
+        match word.strip_prefix('!').and_then(|name| Priority::from_str(name, true).ok()) {
+            Some(found) => priority = Some(found), // If there are several, the last one wins
+            None => words.push(word),
+        }
+    }
+    match priority {
+        Some(_) => (words.join(" "), priority),
+        None => (description.to_string(), None),
+    }
+}
+
+// Check a --due value: it must be a real calendar date written as YYYY-MM-DD (ISO-8601)
+// Like parse_url(), clap calls this while parsing, so bad dates never reach the tasks file
+fn parse_due(value: &str) -> Result<String, String> {
+    let error = || format!("'{}' is not a valid date (use YYYY-MM-DD, e.g. 2025-03-31)", value);
+    // splitn(3, '-') splits into at most 3 pieces: year, month, day
+    let parts: Vec<&str> = value.splitn(3, '-').collect();
+    // Exactly 4 + 2 + 2 digits. Checking the lengths also rejects things like "2025-3-1" or "+2025-03-01"
+    if parts.len() != 3 || parts[0].len() != 4 || parts[1].len() != 2 || parts[2].len() != 2
+        || !parts.iter().all(|part| part.chars().all(|c| c.is_ascii_digit())) {
+        return Err(error());
+    }
+    // The parts are only digits now, so parse() can't fail
+    let year: u32 = parts[0].parse().map_err(|_| error())?;
+    let month: u32 = parts[1].parse().map_err(|_| error())?;
+    let day: u32 = parts[2].parse().map_err(|_| error())?;
+    // Leap years: every 4th year, except every 100th, except every 400th
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return Err(error()), // There is no month 0 or 13
+    };
+    if day == 0 || day > days_in_month {
+        return Err(error());
+    }
+    Ok(value.to_string())
+}
+
+// Create a new task and add it to the list
+// Takes a mutable reference (&mut Vec<Task>) because we change the vector, but the caller keeps ownership
+// Returns a mutable reference to the new task, so the caller can read its ID
+// and fill in optional fields (like the estimate or URL)
+fn add_task(tasks: &mut Vec<Task>, description: String) -> &mut Task {
+    let id = get_next_id(tasks); // Assign next available ID
+    tasks.push(Task {
+        id,
+        description,
+        completed: false, // New tasks start as incomplete
+        estimate_minutes: None, // Optional fields start empty
+        url: None,
+        due: None,
+        priority: Priority::Medium,
+        tags: Vec::new(),
+        created_at: now_timestamp(),
+        completed_at: None, // Set by mark_done()
+    });
+    let index = tasks.len() - 1; // The new task is the last one in the vector
+    &mut tasks[index]
+}
+
+// Mark the task with the given ID as completed
+// Takes a mutable slice (&mut [Task]) because we only change a task, we never add or remove one
+// Returns:
+//   None        - no task has this ID
+//   Some(true)  - the task was marked as done
+//   Some(false) - the task was already done, so nothing changed
+fn mark_done(tasks: &mut [Task], id: u32) -> Option<bool> {
+    // Search for a task with the matching ID
+    // iter_mut() gives mutable references so we can modify the task
+    // find() returns Option: Some(task) if found, None if not found
+    // map() runs the closure only when we got Some(task)
+    tasks.iter_mut().find(|t| t.id == id).map(|task| {
+        let changed = !task.completed; // It only changes if it wasn't completed before
+        task.completed = true; // Mark as completed
+        if changed {
+            task.completed_at = Some(now_timestamp()); // Marking it done again keeps the first time
+        }
+        changed
+    })
+}
+
+// Mark the task with the given ID as not completed (the opposite of mark_done)
+// Returns None if no task has this ID, Some(true) if it was reopened and Some(false) if it wasn't done anyway
+fn mark_undone(tasks: &mut [Task], id: u32) -> Option<bool> {
+    tasks.iter_mut().find(|t| t.id == id).map(|task| {
+        let changed = task.completed; // It only changes if it was completed before
+        task.completed = false;
+        task.completed_at = None; // It isn't done anymore
+        changed
+    })
+}
+
+// Remove the oldest completed tasks so at most `keep` of them are left (config max_completed)
+// Oldest means the earliest completed_at. Tasks completed before timestamps existed count as the oldest of all.
+// Returns the removed tasks, so the caller can tell the user what went away
+fn prune_completed(tasks: &mut Vec<Task>, keep: usize) -> Vec<Task> {
+    // (when it was completed, id) for every completed task. None sorts before any time
+    let mut completed: Vec<(Option<chrono::DateTime<chrono::FixedOffset>>, u32)> = tasks.iter()
+        .filter(|t| t.completed)
+        .map(|t| (t.completed_at.as_deref().and_then(|c| chrono::DateTime::parse_from_rfc3339(c).ok()), t.id))
+        .collect();
+    if completed.len() <= keep {
+        return Vec::new();
+    }
+    completed.sort(); // Oldest first
+    let too_old: Vec<u32> = completed[..completed.len() - keep].iter().map(|(_, id)| *id).collect();
+    // partition() splits the tasks in two vectors: the ones to remove, and the ones to keep
+    // mem::take() moves the vector out of `tasks` (leaving an empty one) so we can consume it
+    let (pruned, kept): (Vec<Task>, Vec<Task>) = std::mem::take(tasks).into_iter().partition(|t| too_old.contains(&t.id));
+    *tasks = kept;
+    pruned
+}
+
+// Delete the task with the given ID
+// Returns true if a task was removed, false if no task had that ID
+fn remove_task(tasks: &mut Vec<Task>, id: u32) -> bool {
+    let original_len = tasks.len(); // Remember how many tasks we had
+    tasks.retain(|t| t.id != id); // retain() keeps only tasks where the condition is true (id != the one we want to remove)
+    tasks.len() < original_len // Compare lengths to know if something was removed
+}
+
+// Move the task with the given ID to a 1-based position in the stored list
+// Positions outside the list are clamped: 0 means the top, anything past the end means the bottom
+// Returns false if no task has that ID
+fn move_task(tasks: &mut Vec<Task>, id: u32, position: usize) -> bool {
+    let Some(index) = tasks.iter().position(|t| t.id == id) else {
+        return false;
+    };
+    let task = tasks.remove(index);
+    // Keep the index between the first and the last place (after removing, len() is the place after the last task)
+    let new_index = position.saturating_sub(1).min(tasks.len());
+    tasks.insert(new_index, task);
+    true
+}
+
+// Put the tasks in the order `list` shows them: most important first (High, then Medium, then Low)
+// sort_by_key() is stable, so tasks with the same priority keep the order they're stored in (see `move`)
+// Reverse flips the order, because Low < Medium < High would otherwise put Low first
+fn sort_for_list(tasks: &mut [Task]) {
+    tasks.sort_by_key(|t| std::cmp::Reverse(t.priority));
+}
+
+// Give the tasks the IDs 1, 2, 3, ... in their current order
+// Returns the (old, new) pairs of the tasks whose ID changed
+fn renumber_tasks(tasks: &mut [Task]) -> Vec<(u32, u32)> {
+    let mut changes = Vec::new();
+    // iter_mut() to change the IDs, zip(1..) pairs each task with the numbers 1, 2, 3, ...
+    for (task, new_id) in tasks.iter_mut().zip(1..) {
+        if task.id != new_id {
+            changes.push((task.id, new_id));
+            task.id = new_id;
+        }
+    }
+    changes
+}
+
+// Run one operation from `doit apply` against the task list
+// Returns Ok((message, changed)) when the operation worked and Err(message) when it didn't,
+// so the caller can decide how to report it. `changed` tells the caller whether the list
+// is actually different now (marking an already-done task done changes nothing)
+fn apply_operation(tasks: &mut Vec<Task>, operation: Operation) -> Result<(String, bool), String> {
+    match operation {
+        Operation::Add { description, estimate_minutes } => {
+            if description.trim().is_empty() {
+                return Err("description is empty".to_string());
+            }
+            let task = add_task(tasks, description);
+            task.estimate_minutes = estimate_minutes;
+            Ok((format!("added task #{}", task.id), true))
+        },
+        Operation::Done { id } => {
+            match mark_done(tasks, id) {
+                Some(true) => Ok((format!("marked task #{} as done", id), true)),
+                Some(false) => Ok((format!("task #{} was already done (no changes)", id), false)),
+                None => Err(format!("task #{} not found", id)),
+            }
+        },
+        Operation::Remove { id } => {
+            if remove_task(tasks, id) {
+                Ok((format!("removed task #{}", id), true))
+            } else {
+                Err(format!("task #{} not found", id))
+            }
+        },
+    }
+}
+
+// Answer one line of `doit rpc` and return the JSON-RPC response
+// The methods reuse the `apply` operations: {"method":"done","params":{"id":3}} is
+// turned into {"op":"done","id":3} and run through apply_operation()
+// Every successful call returns the whole (updated) task list as its result
+fn handle_rpc_request(line: &str) -> serde_json::Value {
+    // Standard JSON-RPC error codes: -32700 bad JSON, -32601 unknown method, -32602 bad params
+    let error = |id: serde_json::Value, code: i32, message: String| {
+        serde_json::json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+    };
+    let request: RpcRequest = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return error(serde_json::Value::Null, -32700, format!("invalid request: {}", e)),
+    };
+    // Load the tasks for every request, so changes made by the normal CLI in the meantime are seen
+    // -32603 is JSON-RPC's "internal error", e.g. the tasks file can't be read
+    let mut tasks = match load_tasks() {
+        Ok(tasks) => tasks,
+        Err(e) => return error(request.id, -32603, e.to_string()),
+    };
+    match request.method.as_str() {
+        "list" => {},
+        "add" | "done" | "remove" => {
+            // Params must be an object (or missing). Add the "op" field so it reads like an `apply` line
+            let mut params = match request.params {
+                serde_json::Value::Object(params) => params,
+                serde_json::Value::Null => serde_json::Map::new(),
+                _ => return error(request.id, -32602, "params must be an object".to_string()),
+            };
+            params.insert("op".to_string(), serde_json::Value::String(request.method.clone()));
+            let operation = match serde_json::from_value::<Operation>(serde_json::Value::Object(params)) {
+                Ok(operation) => operation,
+                Err(e) => return error(request.id, -32602, format!("invalid params: {}", e)),
+            };
+            match apply_operation(&mut tasks, operation) {
+                // Only write the file if something changed
+                Ok((_, true)) => {
+                    if let Err(e) = save_tasks(&tasks) {
+                        return error(request.id, -32603, e.to_string());
+                    }
+                },
+                Ok((_, false)) => {},
+                Err(message) => return error(request.id, -32602, message),
+            }
+        },
+        method => return error(request.id, -32601, format!("unknown method '{}'", method)),
+    }
+    serde_json::json!({ "jsonrpc": "2.0", "id": request.id, "result": tasks })
+}
+
+// List the fields that differ between two versions of the same task
+// Instead of comparing every field by hand, we turn both tasks into JSON objects
+// (serde_json::Value) and compare them key by key. That way new Task fields are
+// picked up automatically without changing this function.
+// Returns lines like: completed: false -> true
+fn task_field_changes(old: &Task, new: &Task) -> Vec<String> {
+    // to_value() can only fail for types that can't become JSON, which Task always can
+    let old = serde_json::to_value(old).unwrap_or_default();
+    let new = serde_json::to_value(new).unwrap_or_default();
+    let mut changes = Vec::new();
+    // as_object() gives us the key/value map of a JSON object (Some) or None for other JSON types
+    if let (Some(old), Some(new)) = (old.as_object(), new.as_object()) {
+        // Collect every key from both sides, so fields that only exist in one file are shown too
+        // BTreeSet keeps the keys sorted and without duplicates
+        let keys: std::collections::BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+        for key in keys {
+            // A missing key is shown as null
+            let before = old.get(key).unwrap_or(&serde_json::Value::Null);
+            let after = new.get(key).unwrap_or(&serde_json::Value::Null);
+            if before != after {
+                changes.push(format!("{}: {} -> {}", key, before, after));
+            }
+        }
+    }
+    changes
+}
+
+// The current local time as an RFC 3339 timestamp, e.g. 2025-03-31T09:15:00+02:00
+// Seconds are enough for tasks, so we leave out the fractions
+fn now_timestamp() -> String {
+    chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+}
+
+// How long ago a task was created, e.g. "today", "1 day old", "12 days old"
+// None if the timestamp is missing (tasks from older files) or can't be read
+fn task_age(created_at: &str) -> Option<String> {
+    let created = chrono::DateTime::parse_from_rfc3339(created_at).ok()?;
+    let days = (chrono::Local::now().fixed_offset() - created).num_days();
+    Some(match days {
+        ..=0 => "today".to_string(), // Also covers a clock that was set back
+        1 => "1 day old".to_string(),
+        n => format!("{} days old", n),
+    })
+}
+
+// The local day a task was finished, read from completed_at
+// None for pending tasks and for tasks completed before timestamps were recorded
+fn completion_date(task: &Task) -> Option<chrono::NaiveDate> {
+    let completed_at = task.completed_at.as_deref()?;
+    let completed = chrono::DateTime::parse_from_rfc3339(completed_at).ok()?;
+    Some(completed.with_timezone(&chrono::Local).date_naive())
+}
+
+// When something last happened to a task: it was completed, or else added
+// None if neither timestamp can be read (tasks from files written before timestamps existed)
+fn last_activity(task: &Task) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+    let created = chrono::DateTime::parse_from_rfc3339(&task.created_at).ok();
+    let completed = task.completed_at.as_deref().and_then(|c| chrono::DateTime::parse_from_rfc3339(c).ok());
+    created.max(completed) // Option's max(): any Some beats None, two Somes compare by time
+}
+
+// Print the completed tasks under one header per day, newest day first (for `list --by-completion-date`)
+fn print_by_completion_date(tasks: Vec<Task>) {
+    // A BTreeMap keeps its keys sorted. None (no timestamp) sorts before every date
+    let mut days: std::collections::BTreeMap<Option<chrono::NaiveDate>, Vec<Task>> = std::collections::BTreeMap::new();
+    for task in tasks.into_iter().filter(|t| t.completed) {
+        days.entry(completion_date(&task)).or_default().push(task);
+    }
+    if days.is_empty() {
+        println!("📝 No completed tasks yet");
+        return;
+    }
+    // The unknown day goes last, so take it out before walking the dates backwards
+    let unknown = days.remove(&None);
+    let groups = days.into_iter().rev().map(|(day, tasks)| (day.map(|d| d.to_string()), tasks));
+    for (header, mut tasks) in groups.chain(unknown.map(|tasks| (None, tasks))) {
+        println!("📅 {}", header.unwrap_or_else(|| "(unknown date)".to_string()));
+        // Latest first inside a day too. RFC 3339 strings from the same day sort by time
+        tasks.sort_by(|a, b| b.completed_at.cmp(&a.completed_at));
+        for task in tasks {
+            println!("  {}", task_line(&task));
+        }
+    }
+}
+
+// Format a number of minutes in a human friendly way
+// Examples: 45 -> "45m", 60 -> "1h", 90 -> "1h 30m"
+// u64 so totals of many estimates fit too
+fn format_minutes(minutes: u64) -> String {
+    let hours = minutes / 60; // Integer division drops the remainder
+    let rest = minutes % 60; // % gives the remainder (the minutes left over)
+    match (hours, rest) {
+        (0, m) => format!("{}m", m),
+        (h, 0) => format!("{}h", h),
+        (h, m) => format!("{}h {}m", h, m),
+    }
+}
+
+// Format one task the way `list` shows it: emoji [id] description (~estimate)
+fn task_line(task: &Task) -> String {
+    // Check if task is completed and set the an emoji
+    // If completed is true, use ✅, otherwise show the priority (🔴 high, 🟡 medium, 🟢 low)
+    let status = if task.completed { "✅".to_string() } else { priority_glyph(task.priority) };
+    // Show the estimate only if the task has one, e.g. " (~1h 30m)"
+    let estimate = match task.estimate_minutes {
+        Some(minutes) => format!(" (~{})", format_minutes(minutes.into())),
+        None => String::new(),
+    };
+    // A 🔗 shows that the task has a URL (open it with `doit open <id>`)
+    let link = if task.url.is_some() { " 🔗" } else { "" };
+    // Show the due date only if the task has one, e.g. " 📅 2025-03-31"
+    let due = match &task.due {
+        Some(date) => format!(" 📅 {}", date),
+        None => String::new(),
+    };
+    // Tags after the description, e.g. " #work #urgent"
+    let tags: String = task.tags.iter().map(|tag| format!(" #{}", tag)).collect();
+    // Colors (only when they're on, see use_color()): the ID in cyan, and completed tasks
+    // dimmed with the description struck through, so the pending ones stand out
+    let id = paint(&format!("[{}]", task.id), "36");
+    if task.completed {
+        let description = paint(&task.description, "2;9"); // 2 = dim, 9 = strikethrough
+        let details = paint(&format!("{}{}{}{}", tags, estimate, due, link), "2");
+        format!("{} {} {}{}", status, id, description, details)
+    } else {
+        format!("{} {} {}{}{}{}{}", status, id, task.description, tags, estimate, due, link)
+    }
+}
+
+// Decide if colors should be used for the given --color choice
+fn color_enabled(choice: ColorChoice) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        // NO_COLOR (https://no-color.org) is a common convention: if it's set to anything, don't use colors.
+        // is_terminal() is false when the output is piped or redirected to a file
+        ColorChoice::Auto => {
+            std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && io::stdout().is_terminal()
+        },
+    }
+}
+
+// The one place every renderer asks "should I print colors?"
+fn use_color() -> bool {
+    // copied() turns Option<&bool> into Option<bool>. If main() didn't set it (it always does), no colors
+    COLOR_ENABLED.get().copied().unwrap_or(false)
+}
+
+// Wrap text in an ANSI color code if colors are on, otherwise return it unchanged
+// Common codes: "31" red, "32" green, "33" yellow, "36" cyan, "2" dim, "1" bold
+// The terminal shows "\x1b[32m" as "start green" and "\x1b[0m" as "back to normal"
+fn paint(text: &str, code: &str) -> String {
+    if use_color() {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    } else {
+        text.to_string()
+    }
+}
+
+// Turn the task list into an org-mode document
+// Each task becomes a headline like:
+//   * TODO [#A] Pay rent
+//     DEADLINE: <2025-03-31>
+// [#A]/[#B]/[#C] are org's priority cookies (A is the highest)
+fn tasks_to_org(tasks: &[Task]) -> String {
+    let mut org = String::new();
+    for task in tasks {
+        let keyword = if task.completed { "DONE" } else { "TODO" };
+        let cookie = match task.priority {
+            Priority::High => "A",
+            Priority::Medium => "B",
+            Priority::Low => "C",
+        };
+        // A headline must be one line, so newlines in a description become spaces
+        let title = task.description.replace('\n', " ");
+        // Org tags go at the end of the headline, like :work:urgent:
+        // They may only contain letters, digits, _, @, # and %, so anything else becomes _
+        let tags = if task.tags.is_empty() {
+            String::new()
+        } else {
+            let cleaned: Vec<String> = task.tags.iter()
+                .map(|tag| tag.chars().map(|c| if c.is_alphanumeric() || "_@#%".contains(c) { c } else { '_' }).collect())
+                .collect();
+            format!(" :{}:", cleaned.join(":"))
+        };
+        org.push_str(&format!("* {} [#{}] {}{}\n", keyword, cookie, title, tags));
+        // Planning lines (DEADLINE) must come right after the headline
+        if let Some(due) = &task.due {
+            org.push_str(&format!("  DEADLINE: <{}>\n", due));
+        }
+        // [[url]] is an org link
+        if let Some(url) = &task.url {
+            org.push_str(&format!("  [[{}]]\n", url));
+        }
+    }
+    org
+}
+
+// Render the tasks as a Markdown checklist (GitHub shows these as checkboxes):
+//   - [ ] Pay rent
+//   - [x] Buy groceries
+fn tasks_to_markdown(tasks: &[Task]) -> String {
+    tasks.iter()
+        .map(|task| {
+            let mark = if task.completed { "x" } else { " " };
+            // A list item must be one line, so newlines in a description become spaces
+            format!("- [{}] {}\n", mark, task.description.replace('\n', " "))
+        })
+        .collect()
+}
+
+// Width of the terminal in characters
+// Most shells export it in the COLUMNS environment variable; if it's missing we assume 80
+fn terminal_width() -> usize {
+    std::env::var("COLUMNS")
+        .ok() // Turn Result into Option (we don't care why it's missing)
+        .and_then(|value| value.parse().ok()) // Parse the text into a number, None if it isn't one
+        .unwrap_or(80)
+}
+
+// Height of the terminal in rows
+// Like COLUMNS for the width, most shells set LINES. If it's missing or invalid, assume the classic 24 rows
+fn terminal_height() -> usize {
+    std::env::var("LINES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(24)
+}
+
+// Print lines one page at a time, like a tiny `less`
+// After each page we wait for a line on stdin (like the chat loop): Enter shows the next page, q stops
+fn print_paged(lines: &[String], page_size: usize) -> io::Result<()> {
+    // chunks() splits the slice into pieces of page_size lines (the last one may be shorter)
+    let mut pages = lines.chunks(page_size).peekable();
+    while let Some(page) = pages.next() {
+        for line in page {
+            println!("{}", line);
+        }
+        // peek() looks at the next page without taking it. No next page means we're done, so don't ask
+        if pages.peek().is_none() {
+            break;
+        }
+        print!("-- more (Enter: next page, q: quit) -- ");
+        io::stdout().flush()?;
+        let mut input = String::new();
+        // read_line() returns 0 bytes at the end of the input (e.g. Ctrl+D), treat that like q
+        if io::stdin().read_line(&mut input)? == 0 || input.trim().eq_ignore_ascii_case("q") {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Draw a text progress bar like [██████░░░░░░░░░░░░░░] for a percentage (0-100)
+fn progress_bar(percent: usize, width: usize) -> String {
+    let filled = percent.min(100) * width / 100; // Integer math rounds down, so 99% never looks full
+    format!("[{}{}]", "█".repeat(filled), "░".repeat(width - filled))
+}
+
+// Shorten text to at most `width` characters, ending with "…" when it was cut
+fn truncate_text(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        text.to_string()
+    } else {
+        let cut: String = text.chars().take(width.saturating_sub(1)).collect();
+        format!("{}…", cut)
+    }
+}
+
+// Draw a table with borders, for example:
+// ┌────┬─────────┬─────────────┐
+// │ ID │ Status  │ Description │
+// ├────┼─────────┼─────────────┤
+// │ 1  │ pending │ Buy milk    │
+// └────┴─────────┴─────────────┘
+// Each column is as wide as its longest value. If the table is wider than `max_width`,
+// the last column (the description) is shortened so the table still fits
+fn render_table(headers: &[&str], rows: &[Vec<String>], max_width: usize) -> String {
+    // Start with the width of each header, then grow it for longer values
+    // chars().count() counts characters, not bytes, so "é" counts as 1
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.chars().count());
+        }
+    }
+
+    // Every column takes its width plus 3 characters ("│ " before and " " after), plus the final "│"
+    let total: usize = widths.iter().map(|w| w + 3).sum::<usize>() + 1;
+    if total > max_width {
+        let last = widths.len() - 1;
+        let others = total - widths[last]; // Space used by everything except the last column's text
+        // Never shrink it below 10 characters, even on a very narrow terminal
+        widths[last] = max_width.saturating_sub(others).max(10);
+    }
+
+    // A horizontal border line like ├────┼──────┤ using the given corner/junction characters
+    let border = |left: &str, middle: &str, right: &str| -> String {
+        let parts: Vec<String> = widths.iter().map(|w| "─".repeat(w + 2)).collect();
+        format!("{}{}{}\n", left, parts.join(middle), right)
+    };
+    // One row of cells, each padded with spaces to the column width
+    let line = |cells: Vec<String>| -> String {
+        let parts: Vec<String> = cells.iter()
+            .zip(&widths)
+            // {:<width$} pads the text on the right up to `width` characters
+            .map(|(cell, &width)| format!(" {:<width$} ", truncate_text(cell, width), width = width))
+            .collect();
+        format!("│{}│\n", parts.join("│"))
+    };
+
+    let mut table = border("┌", "┬", "┐");
+    table.push_str(&line(headers.iter().map(|h| h.to_string()).collect()));
+    table.push_str(&border("├", "┼", "┤"));
+    for row in rows {
+        table.push_str(&line(row.clone()));
+    }
+    table.push_str(&border("└", "┴", "┘"));
+    table
+}
+
+// The error for --context-as-message outside of a chat
+// clap's `requires` can't express "needs --chat, --continue or --session" here, because AskArgs is shared with `summarize`
+fn context_as_message_needs_chat() -> DoitError {
+    DoitError::InvalidInput("--context-as-message only works in a chat (--chat, --continue or --session)".to_string())
+}
+
+// Turn the command line options (AskArgs) into the options used while asking the AI (AskOptions)
+// Returns an error if the HTTP client can't be created
+fn build_ask_options(args: AskArgs) -> Result<AskOptions, Box<dyn std::error::Error>> {
+    check_ollama_url()?; // Every AI command builds its options first, so this runs before any request
+    let config = load_config()?;
+    Ok(AskOptions {
+        client: build_client(args.timeout, Some(args.connect_timeout))?,
+        model: args.model,
+        ndjson_out: args.ndjson_out,
+        context_as_message: args.context_as_message,
+        anonymize: args.anonymize,
+        assistant_name: args.assistant_name,
+        persona: args.persona,
+        trim_blank_lines: args.trim_blank_lines,
+        save_as_task: args.save_as_task,
+        apply_actions: args.apply,
+        apply_without_asking: args.yes,
+        retries_on_empty: if args.retry_on_empty { args.retries } else { 0 }, // 0 turns retrying off
+        // The cursor is drawn with ANSI codes, so it follows the same rules as colors.
+        // It would also garble --ndjson-out, which is meant for programs, not people
+        streaming_cursor: args.show_streaming_cursor && use_color() && !args.ndjson_out,
+        min_priority: args.min_priority,
+        include_completed: args.include_completed,
+        show_context_size: args.show_context_size,
+        prompt_template: None, // Only `ask --template` sets this, after building the options
+        system_prompt: config.system_prompt,
+        no_stream: args.no_stream,
+        // In a file or pipe, escape codes are never wanted
+        strip_ansi: args.strip_ansi || !io::stdout().is_terminal(),
+        ansi_state: std::cell::Cell::new(AnsiState::Text),
+        resume_partial: args.resume_partial,
+        // A counter is only useful for a person watching, so skip it when stderr goes to a file or pipe
+        progress: args.progress && io::stderr().is_terminal(),
+        token_count: std::cell::Cell::new(0),
+        newline_run: std::cell::Cell::new(0), // Nothing printed yet
+    })
+}
+
+// Turn a position in the list into a spreadsheet-style label: 0 -> "A", 25 -> "Z", 26 -> "AA", 27 -> "AB"
+fn placeholder_label(index: usize) -> String {
+    let mut label = String::new();
+    let mut n = index + 1; // Work with 1-based numbers, like spreadsheet columns
+    while n > 0 {
+        n -= 1;
+        // 'A' as u8 is 65, so 65 + 0 = 'A', 65 + 1 = 'B', ...
+        label.insert(0, (b'A' + (n % 26) as u8) as char); // insert at 0 because we build the label from the right
+        n /= 26;
+    }
+    label
+}
+
+// Replace every task description with a placeholder ("Task A", "Task B", ...)
+// and every tag with a numbered one ("tag1", "tag2", ...). The same tag always gets the same
+// placeholder, so the AI can still tell which tasks belong together.
+// Everything else (ID, completed, estimate...) is kept, so the AI still sees the structure of the list.
+// Returns the anonymized JSON for the AI and a legend that maps placeholders back to the real tasks.
+// The legend is only shown locally - it is never sent to the AI.
+fn anonymized_tasks_json(tasks: Vec<Task>) -> (String, Vec<String>) {
+    let mut legend = Vec::new();
+    let mut seen_tags: Vec<String> = Vec::new(); // Real tags in the order we met them; position + 1 is the placeholder number
+    let tasks: Vec<Task> = tasks
+        .into_iter() // into_iter() takes ownership so we can change each task
+        .enumerate() // Pair each task with its position: (0, task), (1, task), ...
+        .map(|(index, mut task)| {
+            let placeholder = format!("Task {}", placeholder_label(index));
+            legend.push(format!("{} = [{}] {}", placeholder, task.id, task.description));
+            task.description = placeholder;
+            task.url = None; // A link can give away what the task is about too
+            // Tags like "acme-layoffs" say as much as the description
+            task.tags = task.tags.iter()
+                .map(|tag| {
+                    let number = match seen_tags.iter().position(|seen| seen == tag) {
+                        Some(index) => index + 1,
+                        None => {
+                            seen_tags.push(tag.clone());
+                            seen_tags.len()
+                        },
+                    };
+                    format!("tag{}", number)
+                })
+                .collect();
+            task
+        })
+        .collect();
+    for (index, tag) in seen_tags.iter().enumerate() {
+        legend.push(format!("tag{} = #{}", index + 1, tag));
+    }
+    // Serializing our own Vec<Task> can't really fail, but fall back to an empty list just in case
+    let json = serde_json::to_string_pretty(&tasks).unwrap_or_else(|_| "[]".to_string());
+    (json, legend)
+}
+
+// Load the tasks the AI should see
+// Completed tasks rarely matter for "what next?", so they're left out unless --include-completed is given.
+// --min-priority leaves out the less important ones too
+fn context_tasks(options: &AskOptions) -> Result<Vec<Task>, Box<dyn std::error::Error>> {
+    Ok(load_tasks()?
+        .into_iter()
+        .filter(|task| options.include_completed || !task.completed)
+        .filter(|task| meets_priority(task, options.min_priority))
+        .collect())
+}
+
+// Build the task list that is sent to the AI, honoring the options (like --anonymize)
+// Used when a question starts and when the chat context is refreshed
+fn task_context_json(options: &AskOptions) -> Result<String, Box<dyn std::error::Error>> {
+    let tasks = context_tasks(options)?;
+    if options.anonymize {
+        let (json, legend) = anonymized_tasks_json(tasks);
+        // Show the mapping on stderr so it doesn't mix with the answer (or with --ndjson-out output)
+        eprintln!("🔒 Sending anonymized tasks to the AI:");
+        for line in legend {
+            eprintln!("   {}", line);
+        }
+        Ok(json)
+    } else {
+        // Turn the tasks back into JSON text for the prompt
+        Ok(serde_json::to_string_pretty(&tasks)?)
+    }
+}
+
+// Is the task important enough for --min-priority? Without a minimum every task is
+fn meets_priority(task: &Task, min_priority: Option<Priority>) -> bool {
+    // is_none_or() is true for None, otherwise it checks the condition
+    min_priority.is_none_or(|min| task.priority >= min) // >= works because Priority derives Ord (Low < Medium < High)
+}
+
+// Main function to handle AI requests
+// Routes to either one-shot(one question) mode or chat mode based on the chat flag
+// Takes references (&str) because we only need to read the data, not own it
+// Returns Result to handle potential errors (network issues, API errors, etc.)
+async fn ask_ai(prompt: &str, tasks_json: &str, chat: bool, options: &AskOptions) -> Result<(), Box<dyn std::error::Error>> {
+    if chat {
+        // User wants conversational mode - use chat endpoint with history
+        ask_chat(prompt, tasks_json, None, &last_chat_path(), options).await
+    } else {
+        // User wants one-shot question - use generate endpoint without history
+        let mut answer = ask_once(prompt, tasks_json, options).await?;
+        // A freshly loaded model sometimes finishes successfully but without any text.
+        // With --retry-on-empty we simply ask again (up to `retries_on_empty` times)
+        let mut retries_left = options.retries_on_empty;
+        while answer.trim().is_empty() && retries_left > 0 {
+            retries_left -= 1;
+            eprintln!("Retrying because the model returned an empty response...");
+            answer = ask_once(prompt, tasks_json, options).await?;
+        }
+        if options.save_as_task {
+            save_answer_as_task(&answer)?;
+        }
+        if options.apply_actions {
+            apply_ai_actions(&answer, options.apply_without_asking)?;
+        }
+        Ok(())
+    }
+}
+
+// Create a new task from an AI answer (--save-as-task)
+// The answer is squeezed onto one line and cut to MAX_SAVED_ANSWER_CHARS so it fits in the list
+fn save_answer_as_task(answer: &str) -> Result<(), Box<dyn std::error::Error>> {
+    // split_whitespace() + join(" ") turns newlines and repeated spaces into single spaces
+    let text = answer.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.is_empty() {
+        eprintln!("Nothing to save: the AI answer was empty");
+        return Ok(());
+    }
+    // Count characters, not bytes: emoji and accented letters take more than one byte
+    let description = if text.chars().count() > MAX_SAVED_ANSWER_CHARS {
+        let cut: String = text.chars().take(MAX_SAVED_ANSWER_CHARS - 1).collect();
+        format!("{}…", cut.trim_end()) // Show that the text was cut
+    } else {
+        text
+    };
+    let mut tasks = load_tasks()?;
+    let id = add_task(&mut tasks, description).id;
+    save_tasks(&tasks)?;
+    println!("✅  Saved the answer as task #{}", id);
+    Ok(())
+}
+
+// Something the AI asked us to do (--apply), written as a JSON block in its answer:
+// ```json
+// {"action": "add", "task": "Write the release notes"}
+// ```
+// serde picks the variant from the "action" field, so unknown actions simply fail to parse
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum AiAction {
+    Add { task: String },
+}
+
+// Find the actions in an AI answer
+// Only fenced code blocks (``` ... ```) are looked at. A block may hold one object, an array of
+// objects or one object per line. Anything that isn't valid JSON is skipped: models often
+// get the format slightly wrong, and a bad block must never stop the good ones
+fn parse_ai_actions(answer: &str) -> Vec<AiAction> {
+    let mut actions = Vec::new();
+    // Splitting on ``` puts the inside of each code block at the odd positions (1, 3, 5, ...)
+    for block in answer.split("```").skip(1).step_by(2) {
+        // Drop a language tag like "json" right after the opening ```
+        let block = match block.split_once('\n') {
+            Some((first, rest)) if !first.trim_start().starts_with(['{', '[']) => rest,
+            _ => block,
+        };
+        if let Ok(list) = serde_json::from_str::<Vec<AiAction>>(block) {
+            actions.extend(list);
+        } else if let Ok(action) = serde_json::from_str::<AiAction>(block) {
+            actions.push(action);
+        } else {
+            // Maybe one object per line
+            let mut skipped = 0;
+            for line in block.lines().filter(|l| !l.trim().is_empty()) {
+                match serde_json::from_str::<AiAction>(line) {
+                    Ok(action) => actions.push(action),
+                    Err(_) => skipped += 1,
+                }
+            }
+            if skipped > 0 {
+                eprintln!("Ignoring {} line(s) of the AI answer that aren't valid actions", skipped);
+            }
+        }
+    }
+    actions
+}
+
+// Carry out the actions from an AI answer (--apply) and tell the user what changed
+// Each action is shown and has to be confirmed first (like `reword`), unless `yes` is set (--yes)
+fn apply_ai_actions(answer: &str, yes: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let actions = parse_ai_actions(answer);
+    if actions.is_empty() {
+        println!("🤖 The AI didn't suggest any changes");
+        return Ok(());
+    }
+    let mut tasks = load_tasks()?;
+    let mut added = 0; // Nothing to save if every suggestion was declined
+    for action in actions {
+        match action {
+            AiAction::Add { task } => {
+                let description = task.trim().to_string();
+                // Same rule as `doit add`: no empty tasks
+                if description.is_empty() {
+                    continue;
+                }
+                // Show the exact text before anything is written
+                println!("➕ {}", description);
+                if !yes && !confirm("Add this task?")? {
+                    continue;
+                }
+                let id = add_task(&mut tasks, description.clone()).id;
+                println!("✅  Added task #{}: {}", id, description);
+                added += 1;
+            },
+        }
+    }
+    if added > 0 {
+        save_tasks(&tasks)?; // Save once at the end, not after every task
+    }
+    Ok(())
+}
+
+// Ask the user a yes/no question and wait for the answer
+// Anything other than "y" or "yes" (case-insensitive) counts as no, so just pressing Enter is a safe "no"
+// Returns io::Result because reading from stdin can fail
+fn confirm(question: &str) -> io::Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?; // Show the question before waiting for input
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    let answer = answer.trim();
+    Ok(answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes"))
+}
+
+// Build the HTTP client used to talk to Ollama
+// timeout: maximum seconds for a whole request, including the streamed answer (None = no limit)
+// connect_timeout: maximum seconds to establish the connection (None = no limit, the commands pass DEFAULT_CONNECT_TIMEOUT)
+// A client can be reused for many requests, so we build it once and pass a reference around
+fn build_client(timeout: Option<u64>, connect_timeout: Option<u64>) -> reqwest::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(seconds) = timeout {
+        builder = builder.timeout(Duration::from_secs(seconds));
+    }
+    if let Some(seconds) = connect_timeout {
+        builder = builder.connect_timeout(Duration::from_secs(seconds));
+    }
+    builder.build()
+}
+
+// Print how much text a request sends to the AI (for --show-context-size)
+// Bytes and characters differ for emoji and accented letters, so we show both
+fn report_context_size(what: &str, text_len: usize, char_count: usize) {
+    eprintln!("📏 {}: {} chars ({} bytes)", what, char_count, text_len);
+}
+
+// Where Ollama runs. DOIT_OLLAMA_URL lets you use a server on another machine, e.g. http://192.168.1.20:11434
+fn ollama_base_url() -> String {
+    let url = std::env::var("DOIT_OLLAMA_URL").unwrap_or_else(|_| "http://localhost:11434".to_string());
+    // Drop trailing slashes, so "http://host:11434/" + "/api/chat" doesn't become "http://host:11434//api/chat"
+    url.trim_end_matches('/').to_string()
+}
+
+// Make sure DOIT_OLLAMA_URL is usable before we send anything
+// Without a scheme ("localhost:11434") reqwest only reports a cryptic "builder error",
+// so we check it up front with the same rules as `add --url`
+fn check_ollama_url() -> Result<(), DoitError> {
+    let url = ollama_base_url();
+    match parse_url(&url) {
+        Ok(_) => Ok(()),
+        Err(_) if !url.contains("://") => Err(DoitError::InvalidInput(format!("invalid Ollama URL '{}': missing http:// scheme", url))),
+        Err(_) => Err(DoitError::InvalidInput(format!("invalid Ollama URL '{}': it should start with http:// or https://", url))),
+    }
+}
+
+// Full URL of an Ollama API endpoint, e.g. ollama_url("/api/chat")
+fn ollama_url(path: &str) -> String {
+    format!("{}{}", ollama_base_url(), path)
+}
+
+// Errors that doit reports itself (as opposed to errors from files, JSON or the network)
+// Each variant has a stable `kind` for --error-json, so scripts can tell them apart without parsing messages.
+// Returning one of these (instead of printing ❌ and returning Ok) also makes doit exit with a non-zero code
+#[derive(Debug)]
+enum DoitError {
+    NotFound(String), // Something the user named doesn't exist (a task, template or file)
+    InvalidInput(String), // The user's input can't be used (empty description, bad template name, ...)
+    InvalidFile(String), // A file doit reads is broken (tasks file, config file, chat session)
+    BatchFailed(String), // Some items of a batch (`done 1 2 3`, `apply`) failed, the others were applied
+}
+
+impl DoitError {
+    // The name --error-json prints as "kind". Don't change these, scripts may rely on them
+    fn kind(&self) -> &'static str {
+        match self {
+            DoitError::NotFound(_) => "not_found",
+            DoitError::InvalidInput(_) => "invalid_input",
+            DoitError::InvalidFile(_) => "invalid_file",
+            DoitError::BatchFailed(_) => "batch_failed",
+        }
+    }
+}
+
+impl std::fmt::Display for DoitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // All variants just carry their message
+        match self {
+            DoitError::NotFound(message)
+            | DoitError::InvalidInput(message)
+            | DoitError::InvalidFile(message)
+            | DoitError::BatchFailed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for DoitError {}
+
+// The error for an ID that no task has, used by every command that takes an ID
+fn task_not_found(id: u32) -> DoitError {
+    DoitError::NotFound(format!("task #{} not found", id))
+}
+
+// Error for "Ollama can't be reached at all" (not running, wrong DOIT_OLLAMA_URL, ...)
+// reqwest's own message is full of internals, so this one says what to check instead.
+// The original error is kept as the source, for anyone who needs the details
+#[derive(Debug)]
+struct OllamaUnreachable {
+    url: String, // The base URL we tried
+    source: reqwest::Error, // What reqwest reported
+}
+
+// Display is the message shown to the user ("Error: Could not reach Ollama ...")
+impl std::fmt::Display for OllamaUnreachable {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Could not reach Ollama at {} — is it running? Start it with `ollama serve`", self.url)
+    }
+}
+
+// Implementing Error lets it travel through `?` as a Box<dyn Error> like any other error
+impl std::error::Error for OllamaUnreachable {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+// Use with map_err() on a request: connection problems become OllamaUnreachable, other errors stay as they are
+fn explain_request_error(error: reqwest::Error) -> Box<dyn std::error::Error> {
+    if error.is_connect() {
+        Box::new(OllamaUnreachable { url: ollama_base_url(), source: error })
+    } else {
+        error.into()
+    }
+}
+
+// POST a request to Ollama, trying again when the problem is probably temporary
+// Right after Ollama starts, the first request may fail to connect or get a 503 while the model loads.
+// Refused connections and 5xx answers are retried with a growing pause (1s, then 2s).
+// 4xx answers (like an unknown model) won't get better by asking again, so they fail right away.
+// A response that is still an error in the end becomes an Err with Ollama's own message,
+// because its body isn't the NDJSON the callers expect and they would just print nothing
+async fn send_to_ollama<T: Serialize>(client: &reqwest::Client, path: &str, body: &T) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+    let mut delay = Duration::from_secs(1);
+    for attempt in 1..=OLLAMA_ATTEMPTS {
+        let last = attempt == OLLAMA_ATTEMPTS;
+        // .json() serializes the body to JSON and sets the Content-Type header, .send() sends it over the network
+        match client.post(ollama_url(path)).json(body).send().await {
+            Ok(res) if res.status().is_server_error() && !last => {
+                eprintln!("Ollama answered {}, retrying in {}s...", res.status(), delay.as_secs());
+            },
+            Ok(res) if !res.status().is_success() => return Err(ollama_status_error(res).await),
+            Ok(res) => return Ok(res),
+            // A connect timeout also counts as is_connect(). Retrying it would wait the whole
+            // --connect-timeout again, so an unreachable host fails once instead of three times
+            Err(error) if error.is_connect() && !error.is_timeout() && !last => {
+                eprintln!("Could not reach Ollama, retrying in {}s...", delay.as_secs());
+            },
+            Err(error) => return Err(explain_request_error(error)),
+        }
+        tokio::time::sleep(delay).await; // Wait without blocking the other tasks (e.g. `reword --concurrency`)
+        delay *= 2; // Exponential backoff: every pause is twice as long as the one before
+    }
+    unreachable!("the last attempt always returns") // The loop returns on its last attempt, so we never get here
+}
+
+// Turn an error response from Ollama into a readable error
+// Ollama explains what went wrong in a JSON body like {"error": "model 'nonexistent' not found"},
+// so we show that text, e.g. "Ollama returned 404 Not Found: model 'nonexistent' not found"
+async fn ollama_status_error(res: reqwest::Response) -> Box<dyn std::error::Error> {
+    let status = res.status();
+    let body = res.text().await.unwrap_or_default(); // No body is fine, the status alone still says something
+    let message = match serde_json::from_str::<OllamaErrorResponse>(&body) {
+        Ok(parsed) => parsed.error,
+        Err(_) => body.trim().to_string(), // Not JSON (e.g. from a proxy): show the text as it is
+    };
+    Box::new(OllamaHttpError { status, message })
+}
+
+// Error for "Ollama answered, but with an error status" (404 unknown model, 500, ...)
+// Its own type (instead of a String) so --error-json can report it as an "http" error
+#[derive(Debug)]
+struct OllamaHttpError {
+    status: reqwest::StatusCode, // e.g. 404 Not Found
+    message: String, // Ollama's explanation (empty if it didn't send one)
+}
+
+impl std::fmt::Display for OllamaHttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.message.is_empty() {
+            write!(f, "Ollama returned {}", self.status)
+        } else {
+            write!(f, "Ollama returned {}: {}", self.status, self.message)
+        }
+    }
+}
+
+impl std::error::Error for OllamaHttpError {}
+
+// Send one prompt to the AI and wait for the complete answer (no streaming)
+// Used by commands that need the whole answer before they can do something with it (like `reword`)
+// Returns the answer text
+async fn generate_text(client: &reqwest::Client, model: &str, prompt: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let request_body = GenerateRequest {
+        model: model.to_string(),
+        prompt: prompt.to_string(),
+        stream: false, // Ask for one complete JSON object instead of NDJSON chunks
+    };
+
+    let response = send_to_ollama(client, "/api/generate", &request_body)
+        .await?
+        .json::<GenerateResponse>() // The whole body is a single GenerateResponse
+        .await?;
+
+    Ok(response.response)
+}
+
+// Ask the AI for a cleaner version of one task description
+// Returns the proposed description with extra whitespace and quotes removed
+async fn reword_description(client: &reqwest::Client, model: &str, description: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let prompt = format!(
+        "Rewrite this todo list task so it is concise and consistent: start with a verb, \
+        keep the meaning, no trailing period. Reply with only the rewritten task.\n\nTask: {}",
+        description
+    );
+    let answer = generate_text(client, model, &prompt).await?;
+    // Models sometimes wrap the answer in quotes, so we trim those off together with whitespace
+    Ok(answer.trim().trim_matches('"').trim().to_string())
+}
+
+// Print one streamed piece of text (a "token") from the AI
+// Normally we print the text as-is so the answer appears word-by-word.
+// With --ndjson-out we wrap it in a compact JSON object on its own line instead:
+//   {"token":"Hel","done":false}
+// This gives other programs the same simple shape no matter which endpoint produced the text.
+fn print_token(token: &str, options: &AskOptions) -> io::Result<()> {
+    if options.progress {
+        options.token_count.set(options.token_count.get() + 1);
+        // When the answer itself goes to a file or pipe, nothing moves on screen, so we redraw a counter instead.
+        // \r moves back to the start of the line, so each count overwrites the previous one
+        if !io::stdout().is_terminal() {
+            eprint!("\r⏳ {} tokens", options.token_count.get());
+        }
+    }
+    // With --strip-ansi (or output to a file) we drop escape codes first
+    let stripped;
+    let token = if options.strip_ansi {
+        stripped = strip_ansi_codes(token, &options.ansi_state);
+        stripped.as_str()
+    } else {
+        token
+    };
+    // With --trim-blank-lines we print a cleaned copy of the token, otherwise the token itself
+    let collapsed;
+    let token = if options.trim_blank_lines {
+        collapsed = collapse_blank_lines(token, &options.newline_run);
+        collapsed.as_str()
+    } else {
+        token
+    };
+    if options.ndjson_out {
+        // serde_json::json! builds a JSON value, and printing it with {} gives compact JSON (one line)
+        // It also takes care of escaping quotes and newlines inside the token
+        println!("{}", serde_json::json!({ "token": token, "done": false }));
+    } else if options.streaming_cursor {
+        // \x1b[K erases the old cursor block, then we print the token and a new blinking (5) block.
+        // \x1b[D moves the terminal cursor one step left, back onto the block, so the next token overwrites it
+        print!("\x1b[K{}\x1b[5m█\x1b[0m\x1b[D", token);
+    } else {
+        print!("{}", token);
+    }
+    // Force immediate display instead of waiting for buffer to fill
+    io::stdout().flush()
+}
+
+// Where strip_ansi_codes() is, between two characters
+// Copy lets Cell hand out the value with get()
+#[derive(Clone, Copy)]
+enum AnsiState {
+    Text, // Normal text, characters are kept
+    Escape, // Just saw ESC (\x1b), the next character tells what kind of code follows
+    Csi, // Inside "ESC [ ... letter", e.g. "\x1b[31m" (red). It ends with a letter or similar (@ to ~)
+    Osc, // Inside "ESC ] ... BEL", e.g. a window title. It ends with BEL (\x07) or ESC
+}
+
+// Remove ANSI escape codes (colors, cursor movement...) from a token
+// `state` remembers if the previous token ended in the middle of a code (see AskOptions)
+fn strip_ansi_codes(token: &str, state: &std::cell::Cell<AnsiState>) -> String {
+    let mut result = String::with_capacity(token.len());
+    for c in token.chars() {
+        let next = match (state.get(), c) {
+            (AnsiState::Text, '\x1b') => AnsiState::Escape,
+            (AnsiState::Text, _) => {
+                result.push(c);
+                AnsiState::Text
+            },
+            (AnsiState::Escape, '[') => AnsiState::Csi,
+            (AnsiState::Escape, ']') => AnsiState::Osc,
+            (AnsiState::Escape, _) => AnsiState::Text, // Two-character codes like "ESC c": drop both
+            (AnsiState::Csi, '@'..='~') => AnsiState::Text, // The final character ends the code
+            (AnsiState::Csi, _) => AnsiState::Csi,
+            (AnsiState::Osc, '\x07') => AnsiState::Text,
+            (AnsiState::Osc, '\x1b') => AnsiState::Escape, // "ESC \" ends it, and Escape drops the '\'
+            (AnsiState::Osc, _) => AnsiState::Osc,
+        };
+        state.set(next);
+    }
+    result
+}
+
+// Remove newlines that would make more than one blank line in a row
+// Two newlines in a row ("\n\n") give one blank line, so we keep at most two.
+// `newline_run` remembers how many newlines came right before this token (see AskOptions)
+fn collapse_blank_lines(token: &str, newline_run: &std::cell::Cell<usize>) -> String {
+    let mut result = String::with_capacity(token.len()); // Same size as the token at most
+    for c in token.chars() {
+        match c {
+            '\n' => {
+                let run = newline_run.get() + 1;
+                newline_run.set(run);
+                if run <= 2 {
+                    result.push(c); // Keep the first two newlines of a run, drop the rest
+                }
+            },
+            '\r' => result.push(c), // Windows style "\r\n" - the '\r' doesn't end the run
+            _ => {
+                newline_run.set(0); // Any other character ends the run of newlines
+                result.push(c);
+            },
+        }
+    }
+    result
+}
+
+// Print whatever marks the end of a streamed answer
+// In plain mode that's just newline(s), in --ndjson-out mode it's a final {"done":true} line
+fn print_stream_end(newlines: &str, options: &AskOptions) {
+    // The answer is over, so the next answer starts a fresh count of newlines (and outside any escape code)
+    options.newline_run.set(0);
+    options.ansi_state.set(AnsiState::Text);
+    if options.progress {
+        // replace() returns the old value and stores 0 for the next answer
+        let tokens = options.token_count.replace(0);
+        if io::stdout().is_terminal() {
+            // The answer is on screen, so add the count to the end of its last line.
+            // stdout and stderr are buffered separately: flush stdout first so the count lands after the text
+            let _ = io::stdout().flush();
+            eprint!(" ({} tokens)", tokens);
+        } else {
+            eprintln!("\r✅ {} tokens", tokens); // Replace the live counter with the final count
+        }
+    }
+    if options.ndjson_out {
+        println!("{}", serde_json::json!({ "done": true }));
+    } else if options.streaming_cursor {
+        print!("\x1b[K{}", newlines); // Erase the cursor block for good
+    } else {
+        print!("{}", newlines);
+    }
+}
+
+// The first sentence of every prompt: who the AI should be
+// Examples:
+//   (no options)                            -> "You are a helpful assistant."
+//   system_prompt in the config file        -> that text, as it is
+//   --assistant-name Max --persona coach    -> "You are Max, a productivity coach who keeps the user focused and motivated."
+// The command line flags win over the config file
+fn assistant_intro(options: &AskOptions) -> String {
+    if let (Some(system_prompt), None, None) = (&options.system_prompt, &options.persona, &options.assistant_name) {
+        return with_apply_instructions(system_prompt.trim().to_string(), options);
+    }
+    let role = match options.persona {
+        None => "a helpful assistant",
+        Some(Persona::Terse) => "a terse productivity assistant who answers in as few words as possible",
+        Some(Persona::Friendly) => "a friendly productivity assistant who is warm and encouraging",
+        Some(Persona::Coach) => "a productivity coach who keeps the user focused and motivated",
+    };
+    let intro = match &options.assistant_name {
+        Some(name) => format!("You are {}, {}.", name, role),
+        None => format!("You are {}.", role),
+    };
+    with_apply_instructions(intro, options)
+}
+
+// Add the --apply instructions to the intro (or return it unchanged without --apply)
+fn with_apply_instructions(intro: String, options: &AskOptions) -> String {
+    if options.apply_actions {
+        // --apply: explain the format so the answer contains blocks we can parse
+        format!(
+            "{} To add a task to the user's list, include a fenced JSON code block with one object per task, \
+             like {{\"action\": \"add\", \"task\": \"task description\"}}.",
+            intro
+        )
     } else {
-        Vec::new() // Return an empty vector if file doesn't exist
+        intro
     }
 }
 
-// Save tasks to the JSON file
-// Takes a reference (&Vec<Task>) instead of taking ownership (Vec<Task>).
-// Why use a reference?
-// 1. Efficiency - we don't need to move or copy the entire vector into this function
-// 2. We only need to READ the tasks to convert them to JSON, not modify or take ownership
-// 3. After calling save_tasks(&tasks), the caller can still use 'tasks' because we just borrowed it
-fn save_tasks(tasks: &Vec<Task>) {
-    let json = serde_json::to_string_pretty(tasks) // Serialize the vector to pretty-formatted JSON string. Returns Result<String, Error>
-        .unwrap(); // Extract the String from Result (panics if error)
-    // Write the JSON string to file. Returns Result<(), Error>
-    // The () or Ok(()) means "unit type" - means the function succeeded but has nothing to return (like void in other languages)
-    fs::write(TASKS_FILE, json)
-        .unwrap(); // If Ok(()), do nothing and continue. If Err(error), panic
+// Build the messages that start every chat: instructions for the AI plus the user's tasks
+// Returns one message (tasks inside the system prompt) or two (with --context-as-message)
+fn context_messages(tasks_json: &str, options: &AskOptions) -> Vec<Message> {
+    match &options.context_as_message {
+        // Default: the task list is part of the system prompt
+        None => vec![
+            Message {
+                role: "system".to_string(),
+                // System message sets the AI's behavior and provides task context
+                content: format!("{} Here are the user's tasks:\n{}", assistant_intro(options), tasks_json),
+            },
+        ],
+        // --context-as-message: the system prompt only sets the behavior and the tasks get their own message.
+        // Some models follow instructions better when the data is kept apart from the instructions.
+        Some(role) => vec![
+            Message {
+                role: "system".to_string(),
+                content: format!("{} The user's tasks are provided in the next message.", assistant_intro(options)),
+            },
+            Message {
+                role: role.clone(), // "user" or "tool", as chosen on the command line
+                content: format!("Here are my tasks:\n{}", tasks_json),
+            },
+        ],
+    }
 }
-// Calculate the next available ID for a new task
-// Takes a reference (&Vec<Task>) instead of taking ownership (Vec<Task>).
-// Why use a reference?
-// 1. Efficiency - we don't need to move or copy the entire vector into this function
-// 2. We only need to READ the tasks to find the highest ID, not modify them
-// 3. After calling get_next_id(&tasks), the caller can still use 'tasks' because we just borrowed it
-// Returns u8 - the next available ID number
-fn get_next_id(tasks: &Vec<Task>) -> u8 {
-    tasks.iter() // Iterate over tasks
-        .map(|t| t.id) // Extract just the IDs (example [1, 3, 5])
-        .max() // Find the highest ID. Returns Option<u8>: Some(max_id) or None if empty
-        .unwrap_or(0) + 1 // Extract the value from Some, or use 0 if None (no tasks exist) and Add 1 to get the next available ID
+
+// Where the last chat is saved when you leave it, e.g. ~/.local/share/doit/last_chat.json
+fn last_chat_path() -> PathBuf {
+    data_dir().join("last_chat.json")
 }
 
-// Load tasks as JSON string for AI context
-// This function reads the tasks file and returns its content as a JSON string.
-// Unlike load_tasks() which deserializes into Vec<Task>, this keeps the data as a string
-// because we need to pass it directly to the AI in the prompt.
-// Returns: Valid JSON string (either task list or empty array "[]")
-fn load_tasks_as_json() -> String {
-    // Try to read the tasks file
-    match fs::read_to_string(TASKS_FILE) {
-        // File was read successfully
-        Ok(content) => {
-            // Validate that the content is valid JSON before using it
-            // We use serde_json::Value as a generic JSON type - it can represent any valid JSON
-            // The turbofish syntax ::<Type> tells from_str what type to deserialize into
-            // from_str returns Result<Value, Error>, and .is_ok() checks if deserialization succeeded
-            // If is_ok() is true, the JSON is valid; if false, it's malformed
-            if serde_json::from_str::<serde_json::Value>(&content).is_ok() {
-                // JSON is valid, return the original content
-                content
-            } else {
-                // JSON is malformed - warn the user and return empty array
-                eprintln!("Warning: Invalid JSON in tasks file, using empty task list");
-                "[]".to_string()
-            }
-        },
-        // File doesn't exist or couldn't be read - return empty array
-        // This happens on first run before any tasks are created
-        Err(_) => "[]".to_string(),
+// Write a chat session to a file as pretty JSON, creating the folder if needed
+fn save_chat_session(path: &Path, session: &ChatSession) -> Result<(), Box<dyn std::error::Error>> {
+    // parent() is the folder the file lives in (None only for paths like "/")
+    if let Some(folder) = path.parent() {
+        fs::create_dir_all(folder)?;
     }
+    // Atomic like the tasks file: a crash mid-write must not leave a broken session,
+    // because load_chat_session() would then quietly start a new chat
+    write_atomically(path, serde_json::to_string_pretty(session)?.as_bytes())?;
+    Ok(())
 }
 
-// Main function to handle AI requests
-// Routes to either one-shot(one question) mode or chat mode based on the chat flag
-// Takes references (&str) because we only need to read the data, not own it
-// Returns Result to handle potential errors (network issues, API errors, etc.)
-async fn ask_ai(prompt: &str, tasks_json: &str, chat: bool) -> Result<(), Box<dyn std::error::Error>> {
-    if chat {
-        // User wants conversational mode - use chat endpoint with history
-        ask_chat(prompt, tasks_json).await
-    } else {
-        // User wants one-shot question - use generate endpoint without history
-        ask_once(prompt, tasks_json).await
+// Read a chat session from a file
+// Returns None if the file doesn't exist or isn't a valid session
+fn load_chat_session(path: &Path) -> Option<ChatSession> {
+    let data = fs::read_to_string(path).ok()?; // ? on an Option returns None early
+    let mut session: ChatSession = serde_json::from_str(&data).ok()?;
+    // The stored context messages aren't used as they are: ask_chat() rebuilds them from the current tasks.
+    // context_len only says how many messages to replace, so a hand-edited file with a number
+    // larger than the conversation just means "replace them all" instead of crashing the slice
+    session.context_len = session.context_len.min(session.messages.len());
+    Some(session)
+}
+
+// Collects the raw bytes of a streamed response and hands out complete lines
+//
+// Why not just a String?
+// 1. A network chunk can end in the middle of a multibyte UTF-8 character (like "é" or an emoji).
+//    Converting each chunk to text on its own would turn those broken halves into �.
+//    We keep raw bytes and only convert a line to text once it is complete.
+// 2. Cutting the processed line off the front of a String with `buffer = buffer[pos..].to_string()`
+//    copies everything that's left, for every single line. With long answers that gets slow.
+//    Instead we just move a `start` index forward, and throw away the used bytes once per chunk.
+struct LineBuffer {
+    bytes: Vec<u8>, // Everything received that hasn't been thrown away yet
+    start: usize, // Where the first unread byte is (everything before it was already handed out)
+}
+
+impl LineBuffer {
+    // Create an empty buffer
+    fn new() -> Self {
+        LineBuffer { bytes: Vec::new(), start: 0 }
+    }
+
+    // Add a chunk of bytes from the network
+    fn push(&mut self, chunk: &[u8]) {
+        // First drop the lines we already handed out, so the buffer doesn't grow forever.
+        // drain(..start) removes them in one go (once per chunk, not once per line)
+        self.bytes.drain(..self.start);
+        self.start = 0;
+        self.bytes.extend_from_slice(chunk);
+    }
+
+    // Take the next complete line (without the '\n'), or None if no full line has arrived yet
+    fn next_line(&mut self) -> Option<String> {
+        let unread = &self.bytes[self.start..];
+        // position() finds the index of the first '\n' byte. The ? returns None if there isn't one
+        let newline_pos = unread.iter().position(|&byte| byte == b'\n')?;
+        // Now the line is complete, so it's safe to turn it into text
+        let line = String::from_utf8_lossy(&unread[..newline_pos]).into_owned();
+        self.start += newline_pos + 1; // Skip past the line and its '\n'
+        Some(line)
     }
 }
 
+// Replace the context messages at the start of a conversation with fresh ones, keep the rest
+// The number of messages can change (e.g. a chat started without --context-as-message and refreshed
+// with it), so we swap the whole range instead of message by message.
+// splice() removes the first `context_len` messages and inserts `fresh` in their place.
+// Returns how many context messages there are now
+fn replace_context(messages: &mut Vec<Message>, context_len: usize, fresh: Vec<Message>) -> usize {
+    let new_len = fresh.len();
+    messages.splice(..context_len, fresh);
+    new_len
+}
+
 // Handle interactive chat mode with conversation history
 // Takes the initial question and current tasks as JSON
+// `history` is a previous conversation to continue (from --continue or --session), or None to start a new one
 // Maintains conversation context so AI remembers previous exchanges
+// The conversation is saved to `session_path` after every answer, so it can be continued later
 // Returns Result to handle errors during the conversation
-async fn ask_chat(initial_prompt: &str, tasks_json: &str) -> Result<(), Box<dyn std::error::Error>> {
+async fn ask_chat(initial_prompt: &str, tasks_json: &str, history: Option<ChatSession>, session_path: &Path, options: &AskOptions) -> Result<(), Box<dyn std::error::Error>> {
     // initialize conversation with system prompt and first user question
     // Vec stores the conversation history - each message is kept for context
-    let mut messages = vec![
-        Message {
-            role: "system".to_string(),
-            // System message sets the AI's behavior and provides task context
-            content: format!("You are a helpful assistant. Here are the user's tasks:\n{}", tasks_json),
+    // We also remember how many messages hold the task context, so /refresh knows which ones to replace
+    let (mut messages, mut context_len) = match history {
+        // Continue where the last chat ended, but with today's tasks and options:
+        // the saved context holds the task list (and persona, placeholders...) from when the chat was saved
+        Some(session) => {
+            let mut messages = session.messages;
+            let context_len = replace_context(&mut messages, session.context_len, context_messages(tasks_json, options));
+            (messages, context_len)
         },
-        Message {
-            role: "user".to_string(),
-            // First user question that initiated chat mode
-            content: initial_prompt.to_string(),
+        // Start a new conversation
+        None => {
+            let messages = context_messages(tasks_json, options);
+            let context_len = messages.len();
+            (messages, context_len)
         },
-    ];
+    };
+    messages.push(Message {
+        role: "user".to_string(),
+        // First user question that initiated chat mode
+        content: initial_prompt.to_string(),
+    });
 
     // Send the first message and get AI response
     // The .await waits for the async operation to complete
     // The ? operator propagates errors up if the request fails
-    let ai_response = send_chat_message(&messages).await?;
+    let ai_response = send_chat_message(&messages, options).await?;
 
     // Add AI's response to conversation history 
     // This allows AI to reference its previous answers in follow-up questions
     messages.push(ai_response);
+    // Save after every answer, so an error later on (a timeout, Ollama going away) doesn't lose the chat
+    save_chat(session_path, context_len, &messages);
     
     // Inform user how to continue or exit the conversation
-    println!("\nType your follow-up questions, or 'exit' to quit.\n");
+    println!("\nType your follow-up questions, '/refresh' to reload your tasks, or 'exit' to quit.\n");
 
     // Enter interactive loop - continues until user types 'exit' or 'quit'
     loop {
@@ -234,7 +2447,11 @@ async fn ask_chat(initial_prompt: &str, tasks_json: &str) -> Result<(), Box<dyn
         // Create an empty String to store whatever the user types
         let mut user_input = String::new();
         // Put the user's typed input into `user_input`
-        io::stdin().read_line(&mut user_input)?;
+        // read_line() returns 0 bytes at the end of the input (Ctrl+D), which means the user is done
+        if io::stdin().read_line(&mut user_input)? == 0 {
+            println!("\nGoodbye!");
+            break;
+        }
 
         // Remove leading/trailing whitespace (including the newline from Enter)
         let user_input = user_input.trim();
@@ -251,6 +2468,22 @@ async fn ask_chat(initial_prompt: &str, tasks_json: &str) -> Result<(), Box<dyn
             continue; // Go back to start of loop, show prompt again
         }
 
+        // /refresh reloads the tasks file, so tasks added or finished in another
+        // terminal during a long chat are visible to the AI
+        if user_input.eq_ignore_ascii_case("/refresh") {
+            // If the file can't be read right now, say so and keep chatting with the old list
+            let tasks_json = match task_context_json(options) {
+                Ok(tasks_json) => tasks_json,
+                Err(e) => {
+                    eprintln!("❌ Couldn't refresh the task list: {}\n", e);
+                    continue;
+                },
+            };
+            context_len = replace_context(&mut messages, context_len, context_messages(&tasks_json, options));
+            println!("🔄 Task list refreshed. The AI now sees your current tasks.\n");
+            continue; // Don't send "/refresh" to the AI
+        }
+
         // Add user's message to conversation history
         messages.push(Message {
             role: "user".to_string(),
@@ -259,123 +2492,152 @@ async fn ask_chat(initial_prompt: &str, tasks_json: &str) -> Result<(), Box<dyn
 
         // Send entire conversation history to AI and get response
         // AI sees all previous messages for context
-        let ai_response = send_chat_message(&messages).await?;
+        let ai_response = send_chat_message(&messages, options).await?;
 
         // Add AI's response to history so it can reference it later
         messages.push(ai_response);
+        save_chat(session_path, context_len, &messages);
     }
 
+    // Save once more on the way out, so a /refresh right before leaving is kept too
+    save_chat(session_path, context_len, &messages);
+
     Ok(())
 }
 
+// Save the conversation so `doit ask --continue` (or the same --session) can pick it up.
+// A failure here shouldn't turn a good chat into an error, so we only warn
+fn save_chat(path: &Path, context_len: usize, messages: &[Message]) {
+    let session = ChatSession { context_len, messages: messages.to_vec() };
+    if let Err(error) = save_chat_session(path, &session) {
+        eprintln!("Warning: could not save the chat session: {}", error);
+    }
+}
+
 // Send conversation to AI and stream the response
 // Takes a slice of messages (the entire conversation history)
 // Returns the complete AI response as a Message for adding to history
 // Returns Result to handle network/API errors
-async fn send_chat_message(messages: &[Message]) -> Result<Message, Box<dyn std::error::Error>> {
+async fn send_chat_message(messages: &[Message], options: &AskOptions) -> Result<Message, Box<dyn std::error::Error>> {
+    if options.show_context_size {
+        // The whole conversation is sent every time, so this grows with each message
+        // sum() adds up the lengths of all messages
+        let bytes: usize = messages.iter().map(|m| m.content.len()).sum();
+        let chars: usize = messages.iter().map(|m| m.content.chars().count()).sum();
+        report_context_size(&format!("Conversation ({} messages)", messages.len()), bytes, chars);
+    }
     // Build the request body /api/chat endpoint
     let request_body = ChatRequest {
-        model: "llama3.2".to_string(),
+        model: options.model.clone(), // The model chosen with --model (or DOIT_MODEL)
         messages: messages.to_vec(), // Copy messages into a Vec for JSON serialization
-        stream: true, // Enable token-by-token streaming
+        stream: !options.no_stream, // Token-by-token streaming, unless --no-stream asks for the whole answer at once
     };
 
-    // Create HTTP client for making requests
-    let client = reqwest::Client::new();
-    // Build and send POST request to Ollama's chat endpoint
-    // .json() serializes request_body to JSON and sets Content-Type header
-    // .send() actually sends the HTTP request over the network
+    // Use the HTTP client that was built from the command line options (timeouts)
+    let client = &options.client;
+    // Send a POST request to Ollama's chat endpoint (see send_to_ollama() for the details)
     // .await waits for the request to complete and response to arrive
-    // ? propagates any network errors (connection failed, timeout, etc.)
-    let res = client.post("http://localhost:11434/api/chat")
-        .json(&request_body)
-        .send()
-        .await?;
+    // ? propagates any network errors (connection failed, timeout, etc.), with a friendly message if Ollama isn't running
+    let started = Instant::now(); // For --profile: time to first token and total streaming time
+    let res = send_to_ollama(client, "/api/chat", &request_body).await?;
 
-    // Get response as a stream of bytes (chunks arrive as AI generates text)
-    let mut stream = res.bytes_stream();
-    // Buffer for incomplete JSON lines (chunks may arrive mid-line)
-    let mut buffer = String::new();
     // Accumulate complete response text for returning to caller
     let mut full_response = String::new();
 
-    // Ollama streams data in NDJSON (“newline-delimited JSON”).
-    // Each complete JSON object is sent as a single line, ending with \n.
-    // A chunk from the network may contain:
-    // - half a JSON object
-    // - 3 JSON objects
-    // - 1.5 NDJSON lines
-    // - or a newline in the middle of a UTF-8 character
-    // Example chunks you might get:
-    // CHUNK 1: 
-    //       {"message":{"content":"Hel"},"do               <- there is NO new line here 
-    // CHUNK 2: 
-    //       ne":false}\n                                   <- here we have a new line 
-    //       {"message":{"content":"lo wo"},"done":false}\n <- here we have a new line 
-    // CHUNK 3: 
-    //       {"message":{"content":"r      <-- there is NO new line here 
-    // CHUNK 4: 
-    //       ld"},"done":false}\n          <-- here we have a new line 
-    //       {"done":true}\n               <-- here we have a new line
-    // Read the incoming response chunk by chunk as the server sends bytes.
-    while let Some(chunk_result) = stream.next().await {
-        // Extract the chunk bytes, or return the error if chunk failed to download
-        let chunk = chunk_result?;
-        // Convert raw bytes to UTF-8 text. `from_utf8_lossy` ensures that even if
-        // the stream splits a multibyte character between chunks, invalid byte
-        // sequences are replaced safely with �.
-        //
-        // Important: this does **not** guarantee we now have a whole JSON object.
-        let text = String::from_utf8_lossy(&chunk);
-        // Add the incoming text fragment to our running buffer.
-        // The buffer now may contain:
-        // - incomplete data from previous chunks
-        // - the new text
-        buffer.push_str(&text);
-
-        // Process all complete NDJSON lines in the buffer.
-        // NDJSON format guarantees that each JSON object ends with a newline '\n'.
-        // So as long as we find a newline in the buffer, we know we have one
-        // complete JSON object ready to parse.
-        while let Some(newline_pos) = buffer.find('\n') {
-            // Extract the substring that represents exactly one NDJSON record.
-            // Everything before the newline is one JSON object.
-            let json_str = buffer[..newline_pos].to_string();
-            // Remove the processed line from the buffer.
-            // Any remaining text (after the newline) stays in the buffer.
-            // This remaining text may be:
-            // - an empty string
-            // - partial JSON waiting for the next chunk
-            // - multiple future records still waiting for more data
-            buffer = buffer[newline_pos + 1..].to_string();
+    if options.no_stream {
+        // --no-stream: the whole answer arrives as one JSON object, so there is nothing to stitch together
+        let response = res.json::<ChatResponse>().await?;
+        record_profile(|p| { p.first_token.get_or_insert(started.elapsed()); });
+        full_response = response.message.content;
+        print_token(&full_response, options)?;
+        print_stream_end("\n\n", options);
+    } else {
+        // Get response as a stream of bytes (chunks arrive as AI generates text)
+        let mut stream = res.bytes_stream();
+        // Buffer for incomplete JSON lines (chunks may arrive mid-line)
+        let mut buffer = LineBuffer::new();
 
-            // Skip empty lines
-            if json_str.trim().is_empty() {
-                continue;
-            }
+        // Ollama streams data in NDJSON (“newline-delimited JSON”).
+        // Each complete JSON object is sent as a single line, ending with \n.
+        // A chunk from the network may contain:
+        // - half a JSON object
+        // - 3 JSON objects
+        // - 1.5 NDJSON lines
+        // - or a newline in the middle of a UTF-8 character
+        // Example chunks you might get:
+        // CHUNK 1: 
+        //       {"message":{"content":"Hel"},"do               <- there is NO new line here 
+        // CHUNK 2: 
+        //       ne":false}\n                                   <- here we have a new line 
+        //       {"message":{"content":"lo wo"},"done":false}\n <- here we have a new line 
+        // CHUNK 3: 
+        //       {"message":{"content":"r      <-- there is NO new line here 
+        // CHUNK 4: 
+        //       ld"},"done":false}\n          <-- here we have a new line 
+        //       {"done":true}\n               <-- here we have a new line
+        // Read the incoming response chunk by chunk as the server sends bytes.
+        while let Some(chunk_result) = stream.next().await {
+            // Extract the chunk bytes, or return the error if chunk failed to download
+            let chunk = match chunk_result {
+                Ok(chunk) => chunk,
+                // --resume-partial: the connection broke after some text arrived. Instead of losing it,
+                // keep what we have (with a note for the model) and let the user ask it to go on
+                Err(error) if options.resume_partial && !full_response.is_empty() => {
+                    print_stream_end("\n\n", options);
+                    eprintln!("⚠️  The answer was cut off ({}). Type \"continue\" to let the model finish it.", error);
+                    full_response.push_str("\n\n[This answer was cut off by a connection error.]");
+                    break; // Leave the loop: there is nothing more to read
+                },
+                Err(error) => return Err(error.into()), // Same as `?`, written out because of the arm above
+            };
+            // Add the incoming raw bytes to our running buffer.
+            // The buffer now may contain:
+            // - incomplete data from previous chunks
+            // - the new bytes
+            //
+            // Important: this does **not** guarantee we now have a whole JSON object.
+            buffer.push(&chunk);
 
-            // Attempt to deserialize the JSON object into our struct ChatResponse.
-            if let Ok(response) = serde_json::from_str::<ChatResponse>(&json_str) {
-                // If the field `message.content` contains text, output it.
-                // The streaming API sends incremental content tokens, so each
-                // NDJSON object usually contains a small piece of text.
-                print!("{}", response.message.content);
-                
-                // Force immediate display instead of waiting for buffer to fill
-                io::stdout().flush()?;
+            // Process all complete NDJSON lines in the buffer.
+            // NDJSON format guarantees that each JSON object ends with a newline '\n'.
+            // So as long as the buffer finds a newline, we get one
+            // complete JSON object ready to parse.
+            // Any remaining bytes (after the last newline) stay in the buffer.
+            // They may be:
+            // - nothing at all
+            // - partial JSON waiting for the next chunk
+            while let Some(json_str) = buffer.next_line() {
+                // Skip empty lines
+                if json_str.trim().is_empty() {
+                    continue;
+                }
+
+                // Attempt to deserialize the JSON object into our struct ChatResponse.
+                if let Ok(response) = serde_json::from_str::<ChatResponse>(&json_str) {
+                    // If the field `message.content` contains text, output it.
+                    // The streaming API sends incremental content tokens, so each
+                    // NDJSON object usually contains a small piece of text.
+                    if !response.message.content.is_empty() {
+                        // get_or_insert() only stores the time if nothing was stored yet, so this keeps the very first token
+                        record_profile(|p| { p.first_token.get_or_insert(started.elapsed()); });
+                    }
+                    print_token(&response.message.content, options)?;
                 
-                // Save the content to build complete response
-                full_response.push_str(&response.message.content);
+                    // Save the content to build complete response
+                    full_response.push_str(&response.message.content);
 
-                // Check if this is the last chunk
-                if response.done {
-                    println!("\n"); // Add final newline after complete response
-                    break; // Exit the inner loop
+                    // Check if this is the last chunk
+                    if response.done {
+                        print_stream_end("\n\n", options); // Add final newline after complete response
+                        break; // Exit the inner loop
+                    }
                 }
             }
         }
     }
 
+    record_profile(|p| p.streaming += started.elapsed());
+
     // Return the complete message for adding to conversation history
     Ok(Message {
         role: "assistant".to_string(),
@@ -385,184 +2647,1079 @@ async fn send_chat_message(messages: &[Message]) -> Result<Message, Box<dyn std:
 
 // Handle one-shot AI question (no conversation history)
 // Takes user's question and current tasks as JSON
-// Streams the response word-by-word and exits
+// Streams the response word-by-word and returns the complete answer text
 // Returns Result to handle network/API errors
-async fn ask_once(prompt: &str, tasks_json: &str) -> Result<(), Box<dyn std::error::Error>> {
+async fn ask_once(prompt: &str, tasks_json: &str, options: &AskOptions) -> Result<String, Box<dyn std::error::Error>> {
     // Build the full prompt with task context and user's question
     // The AI sees both the tasks and the question in one prompt 
-    let full_prompt = format!(
-        "You are a helpful assistant. Here are the user's tasks:\n{}\n\nQuestion: {}", tasks_json, prompt
-    );
+    let full_prompt = match &options.prompt_template {
+        // --template: the saved prompt decides where the tasks and the question go
+        Some(template) if template.contains("{tasks}") => format!(
+            "{} {}", assistant_intro(options), template.replace("{question}", prompt).replace("{tasks}", tasks_json)
+        ),
+        // A template without {tasks} still needs to see the tasks, so they go first like in the normal prompt
+        Some(template) => format!(
+            "{} Here are the user's tasks:\n{}\n\n{}", assistant_intro(options), tasks_json, template.replace("{question}", prompt)
+        ),
+        None => format!(
+            "{} Here are the user's tasks:\n{}\n\nQuestion: {}", assistant_intro(options), tasks_json, prompt
+        ),
+    };
+
+    if options.show_context_size {
+        report_context_size("Prompt with task context", full_prompt.len(), full_prompt.chars().count());
+    }
 
     // Create the request body for /api/generate endpoint
     let request_body = GenerateRequest {
-        model: "llama3.2".to_string(),
+        model: options.model.clone(), // The model chosen with --model (or DOIT_MODEL)
         prompt: full_prompt,
-        stream: true,
+        stream: !options.no_stream, // Token-by-token streaming, unless --no-stream asks for the whole answer at once
     };
 
-    // Create HTTP client for making requests
-    let client = reqwest::Client::new();
-    // Build and send POST request to Ollama's generate endpoint
-    // .json() serializes request_body to JSON and sets Content-Type header
-    // .send() actually sends the HTTP request over the network
+    // Use the HTTP client that was built from the command line options (timeouts)
+    let client = &options.client;
+    // Send a POST request to Ollama's generate endpoint (see send_to_ollama() for the details)
     // .await waits for the request to complete and response to arrive
-    // ? propagates any network errors (connection failed, timeout, etc.)
-    let res = client.post("http://localhost:11434/api/generate")
-        .json(&request_body)
-        .send()
-        .await?;
+    // ? propagates any network errors (connection failed, timeout, etc.), with a friendly message if Ollama isn't running
+    let started = Instant::now(); // For --profile: time to first token and total streaming time
+    let res = send_to_ollama(client, "/api/generate", &request_body).await?;
+
+    // Accumulate the complete response text so we can tell if the model said anything at all
+    let mut full_response = String::new();
 
-    // Get response as a stream of bytes (chunks arrive as AI generates text)
-    let mut stream = res.bytes_stream();
-    // Buffer for incomplete JSON lines (chunks may arrive mid-line)
-    let mut buffer = String::new();
-
-    // Read the incoming response chunk by chunk as the server sends bytes.
-    while let Some(chunk_result) = stream.next().await {
-        // Extract the chunk bytes, or return the error if chunk failed to download
-        let chunk = chunk_result?;
-        // Convert raw bytes to UTF-8 text. `from_utf8_lossy` ensures that even if
-        // the stream splits a multibyte character between chunks, invalid byte
-        // sequences are replaced safely with �.
-        //
-        // Important: this does **not** guarantee we now have a whole JSON object.
-        let text = String::from_utf8_lossy(&chunk);
-        // Add the incoming text fragment to our running buffer.
-        // The buffer now may contain:
-        // - incomplete data from previous chunks
-        // - the new text
-        buffer.push_str(&text);
+    if options.no_stream {
+        // --no-stream: the whole answer arrives as one JSON object, so there is nothing to stitch together
+        let response = res.json::<GenerateResponse>().await?;
+        record_profile(|p| { p.first_token.get_or_insert(started.elapsed()); });
+        full_response = response.response;
+        print_token(&full_response, options)?;
+        print_stream_end("\n", options);
+    } else {
+        // Get response as a stream of bytes (chunks arrive as AI generates text)
+        let mut stream = res.bytes_stream();
+        // Buffer for incomplete JSON lines (chunks may arrive mid-line)
+        let mut buffer = LineBuffer::new();
+
+        // Read the incoming response chunk by chunk as the server sends bytes.
+        while let Some(chunk_result) = stream.next().await {
+            // Extract the chunk bytes, or return the error if chunk failed to download
+            let chunk = chunk_result?;
+            // Add the incoming raw bytes to our running buffer.
+            // Important: this does **not** guarantee we now have a whole JSON object.
+            buffer.push(&chunk);
         
-        // Process all complete NDJSON lines in the buffer.
-        while let Some(newline_pos) = buffer.find('\n') {
-            // Extract the substring that represents exactly one NDJSON record.
-            // Everything before the newline is one JSON object.
-            let json_str = buffer[..newline_pos].to_string();
-            // Remove the processed line from the buffer.
-            // Any remaining text (after the newline) stays in the buffer.
-            buffer = buffer[newline_pos + 1..].to_string();
+            // Process all complete NDJSON lines in the buffer.
+            // Each line we get back is exactly one NDJSON record (one JSON object).
+            while let Some(json_str) = buffer.next_line() {
             
-            // Skip empty lines
-            if json_str.trim().is_empty() {
-                continue;
-            }
+                // Skip empty lines
+                if json_str.trim().is_empty() {
+                    continue;
+                }
 
-            // Attempt to deserialize the JSON object into our struct GenerateResponse.
-            if let Ok(response) = serde_json::from_str::<GenerateResponse>(&json_str) {
-                // If the field `message.content` contains text, output it.
-                // The streaming API sends incremental content tokens, so each
-                // NDJSON object usually contains a small piece of text.
-                print!("{}", response.response);
+                // Attempt to deserialize the JSON object into our struct GenerateResponse.
+                if let Ok(response) = serde_json::from_str::<GenerateResponse>(&json_str) {
+                    // If the field `message.content` contains text, output it.
+                    // The streaming API sends incremental content tokens, so each
+                    // NDJSON object usually contains a small piece of text.
+                    if !response.response.is_empty() {
+                        record_profile(|p| { p.first_token.get_or_insert(started.elapsed()); });
+                    }
+                    print_token(&response.response, options)?;
 
-                // Force immediate display instead of waiting for buffer to fill
-                io::stdout().flush()?;
+                    // Save the content to build complete response
+                    full_response.push_str(&response.response);
                 
-                // Check if this is the last chunk (streaming is complete)
-                if response.done {
-                    println!(); // Final newline
-                    break;
+                    // Check if this is the last chunk (streaming is complete)
+                    if response.done {
+                        print_stream_end("\n", options); // Final newline
+                        break;
+                    }
                 }
             }
         }
     }
 
-    Ok(()) //Everything went fine - no value to return
+    record_profile(|p| p.streaming += started.elapsed());
+
+    // The request worked but no text came back. This usually means a wrong model name or endpoint,
+    // so we say so on stderr instead of exiting silently
+    if full_response.trim().is_empty() {
+        eprintln!("Warning: Received empty response from model; check model name and endpoint");
+    }
+
+    Ok(full_response) // Return the complete answer so the caller can use it (e.g. --save-as-task)
 }
 
-#[tokio::main] // Needed so we can use async/await inside main()
-async fn main() -> Result<(), Box<dyn std::error::Error>> { // Return any error or () on success
-    // Parse the command-line arguments provided by the user and create a CLI instance.
-    // Example: User types in terminal: `doit add "Buy milk"`
-    //   - `doit` is the program name
-    //   - `add` is the subcommand
-    //   - `"Buy milk"` is the parameter
-    // This line (CLI::parse()) reads all of that, validates it, and stores it in the `cli` variable.
-    // This is where clap does all the work automatically for us.
-    let cli = CLI::parse();
+// Turn a version string like "0.1.1" into numbers [0, 1, 1] so versions can be compared
+// Comparing the strings directly would be wrong: "0.10.0" < "0.9.0" as text
+// Anything after '-' or '+' (like "-beta" or "+build") is ignored
+#[cfg(feature = "self-update")]
+fn parse_version(version: &str) -> Vec<u64> {
+    version.split(['-', '+']) // Split off pre-release and build metadata
+        .next() // Keep only the "x.y.z" part
+        .unwrap_or("")
+        .split('.') // ["0", "1", "1"]
+        .map(|part| part.parse().unwrap_or(0)) // Turn each part into a number (0 if it isn't one)
+        .collect()
+}
+
+// Check crates.io for a newer release and offer to install it
+// `yes` skips the confirmation question
+#[cfg(feature = "self-update")]
+async fn self_update(yes: bool) -> Result<(), Box<dyn std::error::Error>> {
+    // env! reads values that Cargo sets at compile time, so these are baked into the binary
+    let name = env!("CARGO_PKG_NAME");
+    let current = env!("CARGO_PKG_VERSION");
+
+    // crates.io rejects requests without a User-Agent, so we build a client that sends one
+    let client = reqwest::Client::builder()
+        .user_agent(format!("{}/{}", name, current))
+        .build()?;
+    let url = format!("https://crates.io/api/v1/crates/{}", name);
+    let latest = client.get(&url)
+        .send()
+        .await?
+        .error_for_status()? // Turn 404/500 responses into errors instead of parsing them
+        .json::<CratesIoResponse>() // Deserialize the JSON body into our struct
+        .await?
+        .krate
+        .max_version;
+
+    // Vec<u64> compares element by element, so [0, 2, 0] > [0, 1, 9]
+    if parse_version(&latest) <= parse_version(current) {
+        println!("✅ doit is up to date (version {})", current);
+        return Ok(());
+    }
+
+    println!("⬆️  New version available: {} -> {}", current, latest);
+    let command = format!("cargo install {} --force", name);
+
+    // Ask before installing, unless the user passed --yes
+    if !yes && !confirm(&format!("Run `{}` now?", command))? {
+        println!("To update later, run: {}", command);
+        return Ok(());
+    }
+
+    // Run cargo as a child process and wait for it to finish
+    let status = std::process::Command::new("cargo")
+        .args(["install", name, "--force"])
+        .status()?;
+    if status.success() {
+        println!("✅ Updated doit to version {}", latest);
+    } else {
+        eprintln!("❌ Update failed. You can try running it yourself: {}", command);
+    }
+    Ok(())
+}
+
+// Give an error a short machine readable category for --error-json
+// Our errors are Box<dyn Error>, which can hold any error type. downcast_ref::<T>() checks
+// whether the boxed error is really a T and gives us Some(&T) if it is
+fn error_kind(error: &(dyn std::error::Error + 'static)) -> &'static str {
+    if let Some(error) = error.downcast_ref::<DoitError>() {
+        error.kind() // not_found, invalid_input, invalid_file or batch_failed
+    } else if error.downcast_ref::<io::Error>().is_some() {
+        "io" // Reading/writing files or the terminal failed
+    } else if error.downcast_ref::<serde_json::Error>().is_some() {
+        "json" // Invalid JSON
+    } else if error.downcast_ref::<OllamaUnreachable>().is_some() {
+        "connection" // Ollama isn't running or the URL is wrong
+    } else if error.downcast_ref::<OllamaHttpError>().is_some() {
+        "http" // Ollama answered with an error status (like an unknown model)
+    } else if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+        // Network errors: tell apart "couldn't connect" and "took too long" from other HTTP problems
+        if error.is_connect() {
+            "connection"
+        } else if error.is_timeout() {
+            "timeout"
+        } else {
+            "http"
+        }
+    } else {
+        "other"
+    }
+}
+
+// Run the subcommand the user chose
+// Every error is returned with `?` and handled in one place in main()
+async fn run(command: Commands) -> Result<(), Box<dyn std::error::Error>> { // Return any error or () on success
     // Match on which subcommand the user chose and execute the corresponding action
-    match cli.command {
+    match command {
         // User use the 'list' command. Display all tasks
-        Commands::List => { 
+        Commands::List { fold_completed, completed, print0, table, json, tag, no_due, paginate, age, by_completion_date } => { 
+            let fold_completed = fold_completed && !completed; // --completed always shows them
             // Get the tasks from the file and save them into a vector
             // The 'tasks' variable now holds all our tasks as Vec<Task>
-            let tasks =  load_tasks();
+            let mut tasks =  load_tasks()?;
+            // --tag: keep only the tasks that carry this tag
+            if let Some(tag) = &tag {
+                // Compare normalized, so --tag Work also finds tasks saved as "work" (or before tags were normalized)
+                let config = load_config()?;
+                let wanted = config.normalize_tag(tag);
+                tasks.retain(|t| t.tags.iter().any(|t| config.normalize_tag(t) == wanted));
+            }
+            // --no-due: only what still needs a date. Completed tasks don't need one anymore
+            if no_due {
+                tasks.retain(|t| !t.completed && t.due.is_none());
+            }
+            // --by-completion-date: a "what did I finish?" view, one header per day
+            if by_completion_date {
+                print_by_completion_date(tasks);
+                return Ok(());
+            }
+            // --json: all tasks with all fields, in the order they are stored (no sorting or folding)
+            if json {
+                println!("{}", serde_json::to_string_pretty(&tasks)?);
+                return Ok(());
+            }
+            sort_for_list(&mut tasks); // Most important first
+            // --print0: machine friendly output for shell scripts.
+            // Each value ends with a NUL byte (\0) instead of a newline, so a description
+            // that contains a newline can't be mistaken for two values by tools like `xargs -0`
+            if let Some(field) = print0 {
+                // lock() gives us stdout once for the whole loop instead of locking it on every write
+                let mut out = io::stdout().lock();
+                // Folded tasks are left out completely: there is no summary line in this format,
+                // and a script piping IDs into `doit done` shouldn't get the finished ones
+                for task in tasks.iter().filter(|t| !(fold_completed && t.completed)) {
+                    if field == "description" {
+                        write!(out, "{}\0", task.description)?;
+                    } else {
+                        write!(out, "{}\0", task.id)?;
+                    }
+                }
+                out.flush()?;
+                return Ok(()); // Skip the decorated list
+            }
             if tasks.is_empty() {
-                println!("📝 No tasks yet!"); // Show message if there is no tasks
+                match &tag {
+                    Some(tag) => println!("📝 No tasks tagged #{}", tag), // The list isn't empty, the filter just matched nothing
+                    None if no_due => println!("📅 Every pending task has a due date"), // The filter matched nothing
+                    None => println!("📝 No tasks yet!"), // Show message if there is no tasks
+                }
+            } else if table { // --table: aligned columns instead of the emoji list
+                // Build one row of text cells per task
+                // Plain words are used for the status because emoji have unpredictable widths in terminals
+                let rows: Vec<Vec<String>> = tasks.iter()
+                    .filter(|t| !(fold_completed && t.completed)) // Hide completed tasks when folding
+                    .map(|t| vec![
+                        t.id.to_string(),
+                        if t.completed { "done".to_string() } else { "pending".to_string() },
+                        format!("{:?}", t.priority).to_lowercase(), // {:?} prints the variant name, e.g. "High"
+                        t.estimate_minutes.map(|m| format_minutes(m.into())).unwrap_or_default(), // Empty cell if no estimate
+                        t.due.clone().unwrap_or_default(), // Empty cell if no due date
+                        t.tags.join(" "),
+                        t.description.clone(),
+                    ])
+                    .collect();
+                print!("{}", render_table(&["ID", "Status", "Priority", "Estimate", "Due", "Tags", "Description"], &rows, terminal_width()));
+                // Same summary as the normal list, so folded tasks don't vanish without a word
+                let folded = tasks.len() - rows.len();
+                if folded > 0 {
+                    println!("✅ {} completed (use --completed to show)", folded);
+                }
             } else { // if there are tasks
-                println!("🗒️  Todo List:"); 
+                // Collect the lines first, so --paginate can show them page by page
+                let mut lines = vec!["🗒️  Todo List:".to_string()];
+                let mut folded = 0; // How many completed tasks we hid because of --fold-completed
                 for task in tasks { // Loop through each task in the vector
-                    // Check if task is completed and set the an emoji
-                    // If completed is true, use ✅, otherwise use ⬜
-                    let status = if task.completed { "✅" } else { "⬜" };
-                    println!("  {} [{}] {}", status, task.id, task.description); // Display: emoji [id] description
+                    // When folding, don't print completed tasks - just count them
+                    if fold_completed && task.completed {
+                        folded += 1;
+                        continue; // Skip to the next task
+                    }
+                    let mut line = format!("  {}", task_line(&task)); // Display: emoji [id] description (~estimate)
+                    // --age: only pending tasks, a finished task isn't getting any older
+                    if age && !task.completed {
+                        if let Some(age) = task_age(&task.created_at) {
+                            line.push_str(&format!(" ({})", paint(&age, "2"))); // dimmed
+                        }
+                    }
+                    lines.push(line);
+                }
+                // Show one summary line instead of all the completed tasks
+                if folded > 0 {
+                    lines.push(format!("  ✅ {} completed (use --completed to show)", folded));
+                }
+                // Paging only makes sense when a person reads the output, not when it's piped or redirected
+                if paginate && io::stdout().is_terminal() && io::stdin().is_terminal() {
+                    print_paged(&lines, terminal_height().saturating_sub(1).max(1))?; // Keep one row for the prompt
+                } else {
+                    for line in lines {
+                        println!("{}", line);
+                    }
+                }
+            }
+        },
+        // User use the 'recent' command. Show what changed last, pending or done
+        Commands::Recent { limit } => {
+            let mut tasks = load_tasks()?;
+            // Newest first by the last thing that happened to the task: added, or completed if it was.
+            // Tasks from older files have no timestamps and end up last
+            tasks.sort_by_key(|t| std::cmp::Reverse(last_activity(t)));
+            if tasks.is_empty() {
+                println!("📝 No tasks yet!");
+            }
+            for task in tasks.iter().take(limit) {
+                println!("  {}", task_line(task));
+            }
+        },
+        // User use the 'search' command with a parameter. Show matching tasks
+        Commands::Search { query } => {
+            // Lowercase both sides so "Milk" finds "buy milk"
+            let query = query.to_lowercase();
+            let matches: Vec<Task> = load_tasks()?
+                .into_iter()
+                .filter(|t| t.description.to_lowercase().contains(&query))
+                .collect();
+            if matches.is_empty() {
+                println!("🔍 No matches");
+            } else {
+                for task in &matches {
+                    println!("  {}", task_line(task)); // Same format as `doit list`
                 }
             }
         },
         // User use the 'add' command with a parameter. Create a new task
-        Commands::Add { task } => {
+        Commands::Add { task, estimate, url, due, priority, tag } => {
+            let config = load_config()?;
+            // "!high" in the text is the most specific choice, so it goes first:
+            // inline marker > --priority > DOIT_DEFAULT_PRIORITY (clap fills `priority` from it) > config file > medium
+            let (task, inline_priority) = take_inline_priority(&task);
+            let priority = inline_priority.or(priority).or(config.default_priority).unwrap_or_default();
+            // A blank task can't be told apart from others in the list, so don't store it
+            if task.trim().is_empty() {
+                return Err(DoitError::InvalidInput("please provide a task description".to_string()).into()); // Return early without saving
+            }
             // Get the tasks from the file and save them to a mutable vector
             // We use 'mut' (mutable) because we will modify this vector later (by adding a new task)
-            let mut tasks = load_tasks(); // Load tasks (mutable because we'll add to it)
-            let new_task = Task { // Creata a new Task according to user's parameter
-                id: get_next_id(&tasks), // Assign next available ID
-                // .clone() creates a copy of 'task' string because we use it again in println! below
-                // Without .clone(), 'task' would be moved here and we couldn't use it later
-                // .clone() lets us use the same string in two places
-                description: task.clone(),
-                completed: false, // New tasks start as incomplete
-            };
-            tasks.push(new_task); // Add the new task to the vector
-            save_tasks(&tasks); // Save the updated list to file
+            let mut tasks = load_tasks()?; // Load tasks (mutable because we'll add to it)
+            // Create a new Task according to user's parameter and add it to the vector
+            // .clone() creates a copy of 'task' string because we use it again in println! below
+            // Without .clone(), 'task' would be moved here and we couldn't use it later
+            // .clone() lets us use the same string in two places
+            let new_task = add_task(&mut tasks, task.clone());
+            // These are None unless the user passed --estimate / --url / --due
+            new_task.estimate_minutes = estimate;
+            new_task.url = url;
+            new_task.due = due;
+            new_task.priority = priority;
+            // Normalize the tags and drop repeats, so `--tag Work --tag work` gives one tag
+            for tag in tag {
+                let tag = config.normalize_tag(&tag);
+                if !new_task.tags.contains(&tag) {
+                    new_task.tags.push(tag);
+                }
+            }
+            save_tasks(&tasks)?; // Save the updated list to file
             println!("✅  Adding task: {}", task); // Show a successful message
         },
+        // User use the 'open' command with a parameter. Open the task's URL in the browser
+        Commands::Open { id } => {
+            let tasks = load_tasks()?;
+            match tasks.iter().find(|t| t.id == id) {
+                Some(Task { url: Some(url), .. }) => { // The task exists and has a URL
+                    println!("🔗 Opening {}", url);
+                    open::that(url)?; // Ask the OS to open the URL with the default browser
+                },
+                Some(_) => return Err(DoitError::NotFound(format!("task #{} has no URL", id)).into()),
+                None => return Err(task_not_found(id).into()),
+            }
+        },
+        // User use the 'next' command. Show the single task to focus on
+        Commands::Next { done } => {
+            let mut tasks = load_tasks()?;
+            // The next task is the most important pending one: highest priority first, then the
+            // earliest due date (tasks without one come after those with one), then the lowest ID.
+            // min_by_key() compares the tuples field by field and returns None when there are no pending tasks
+            let Some(id) = tasks.iter()
+                .filter(|t| !t.completed)
+                .min_by_key(|t| (
+                    std::cmp::Reverse(t.priority), // Reverse: High should come first, but min() looks for the smallest
+                    t.due.is_none(), // false < true, so tasks with a due date win
+                    t.due.clone(), // "YYYY-MM-DD" sorts by date when compared as text
+                    t.id,
+                ))
+                .map(|t| t.id)
+            else {
+                println!("🎉 Nothing left to do!");
+                return Ok(());
+            };
+            if done {
+                mark_done(&mut tasks, id);
+                save_tasks(&tasks)?;
+            }
+            // Find the task again to print it (after --done it shows as ✅)
+            if let Some(task) = tasks.iter().find(|t| t.id == id) {
+                println!("{}", task_line(task));
+            }
+        },
+        Commands::Stats => {
+            let tasks = load_tasks()?;
+            let total = tasks.len();
+            let completed = tasks.iter().filter(|t| t.completed).count();
+            let pending = total - completed;
+            // Avoid dividing by zero when the list is empty
+            let percent = if total == 0 { 0 } else { completed * 100 / total };
+            println!("📋 Total:     {}", total);
+            println!("✅ Completed: {}", completed);
+            println!("⬜ Pending:   {}", pending);
+            println!("{} {}%", progress_bar(percent, 20), percent);
+            // Estimate totals for all tasks and for the pending ones.
+            // Each estimate is a u32, but adding many of them could go past u32::MAX, so we add them up as u64
+            let estimated_total: u64 = tasks.iter()
+                .filter_map(|t| t.estimate_minutes)
+                .map(u64::from)
+                .sum();
+            let estimated_left: u64 = tasks.iter()
+                .filter(|t| !t.completed)
+                .filter_map(|t| t.estimate_minutes)
+                .map(u64::from)
+                .sum();
+            // Only mention estimates if some task has one
+            if estimated_total > 0 {
+                println!("⏱  Estimated time: {} in total, {} left", format_minutes(estimated_total), format_minutes(estimated_left));
+            }
+        },
         // User use the 'done' command with a parameter. Mark a task as completed
-        Commands::Done { id } => {
+        Commands::Done { ids, fail_fast } => {
             // Get the tasks from the file and save them to a mutable vector
             // We use 'mut' (mutable) because we will modify this vector later (by changing the status)
-            let mut tasks = load_tasks(); // Load tasks (mutable because we'll modify one)
-            // Search for a task with the matching ID
-            // iter_mut() gives mutable references so we can modify the task
-            // find() returns Option: Some(task) if found, None if not found
-            if let Some(task) = tasks.iter_mut().find(|t| t.id == id) { // With Some(task) we extract the Some value to a task variable to use it in the if block.
-                task.completed = true; // Mark as completed
-                save_tasks(&tasks);// Save changes to file
-                println!("✔️  Marked task #{} as done", id); // Display successful message
-            } else {
-                println!("❌ Task #{} not found", id); // If no task found show no found message
+            let mut tasks = load_tasks()?; // Load once, however many IDs were given
+            let mut changed = false; // Only save if at least one task actually changed
+            let batch = ids.len() > 1; // The summary is only worth printing for more than one ID
+            let mut succeeded = 0; // Count results for the summary, like `apply` does
+            let mut failed = 0;
+            for id in ids {
+                // mark_done() tells us if the task was found, and if it actually changed
+                match mark_done(&mut tasks, id) {
+                    Some(true) => {
+                        changed = true;
+                        succeeded += 1;
+                        println!("✔️  Marked task #{} as done", id); // Display successful message
+                    },
+                    // The task was already done. Nothing changed, but that's not a failure
+                    Some(false) => {
+                        succeeded += 1;
+                        println!("✔️  Task #{} is already done (no changes)", id);
+                    },
+                    None => {
+                        failed += 1;
+                        println!("❌ Task #{} not found", id); // If no task found show no found message
+                        if fail_fast {
+                            println!("stopping at the first error (--fail-fast)");
+                            break;
+                        }
+                    },
+                }
+            }
+            // Write the file a single time at the end, and only if something changed
+            if changed {
+                // max_completed in the config: drop the oldest completed tasks beyond that count
+                if let Some(keep) = load_config()?.max_completed {
+                    for task in prune_completed(&mut tasks, keep) {
+                        println!("🧹 Pruned old completed task #{}: {}", task.id, task.description);
+                    }
+                }
+                save_tasks(&tasks)?;
+            }
+            if batch {
+                println!("{} succeeded, {} failed", succeeded, failed);
+            }
+            // Returning an error makes doit exit with a non-zero code, so scripts can notice the failures
+            if failed > 0 {
+                return Err(DoitError::BatchFailed(format!("{} task(s) not found", failed)).into());
+            }
+        },
+        // User use the 'undone' command with a parameter. Reopen a completed task
+        Commands::Undone { id } => {
+            let mut tasks = load_tasks()?;
+            match mark_undone(&mut tasks, id) {
+                Some(true) => {
+                    save_tasks(&tasks)?;
+                    println!("↩️  Marked task #{} as not done", id);
+                },
+                Some(false) => println!("↩️  Task #{} isn't done (no changes)", id),
+                None => return Err(task_not_found(id).into()), // Same message as `done`
             }
         },
         // User use the 'remove' command with a parameter. Delete a task
         Commands::Remove { id } => {
             // Get the tasks from the file and save them to a mutable vector
             // We use 'mut' (mutable) because we will modify this vector later (by removing a task)
-            let mut tasks = load_tasks(); // Load tasks (mutable because we'll remove one)
-            let original_len = tasks.len(); // Remember how many tasks we had
-            tasks.retain(|t| t.id != id); // retain() keeps only tasks where the condition is true (id != the one we want to remove)
-            // Check if a task was actually removed by comparing lengths. No need to save again the same vector if nothing removed
-            if tasks.len() < original_len {
-                save_tasks(&tasks); // Save the updated list to file
+            let mut tasks = load_tasks()?; // Load tasks (mutable because we'll remove one)
+            // remove_task() returns true only if a task was actually removed. No need to save again the same vector if nothing removed
+            if remove_task(&mut tasks, id) {
+                save_tasks(&tasks)?; // Save the updated list to file
                 println!("🗑️  Removed task #{}", id); // Display successful message
             } else {
-                println!("❌ Task #{} not found", id); // If no task found show no found message
+                return Err(task_not_found(id).into()); // If no task found return a not found error
+            }
+        },
+        // User use the 'move' command. Change where a task shows up in the list (the ID stays the same)
+        Commands::Move { id, position } => {
+            let mut tasks = load_tasks()?;
+            if !move_task(&mut tasks, id, position) {
+                return Err(task_not_found(id).into());
+            }
+            save_tasks(&tasks)?;
+            // `list` shows higher priorities first, so report where the task really shows up there,
+            // which isn't always the position the user asked for
+            let mut shown = tasks.clone();
+            sort_for_list(&mut shown);
+            let shown_position = shown.iter().position(|t| t.id == id).map_or(0, |index| index + 1);
+            let wanted = position.clamp(1, tasks.len()); // Same clamping as move_task() (the list isn't empty here)
+            if shown_position == wanted {
+                println!("↕️  Moved task #{}, it's now number {} in the list", id, shown_position);
+            } else {
+                println!("↕️  Moved task #{}, it's now number {} in the list (tasks are sorted by priority first)", id, shown_position);
+            }
+        },
+        // User use the 'renumber' command. Close the gaps in the IDs
+        Commands::Renumber => {
+            let mut tasks = load_tasks()?;
+            let changes = renumber_tasks(&mut tasks);
+            if changes.is_empty() {
+                println!("🔢 IDs are already sequential (no changes)");
+            } else {
+                save_tasks(&tasks)?;
+                println!("🔢 Renumbered {} task(s):", changes.len());
+                for (old_id, new_id) in changes {
+                    println!("  #{} -> #{}", old_id, new_id);
+                }
+            }
+        },
+        // User use the 'clear' command. Delete the completed tasks (or everything with --all)
+        Commands::Clear { all } => {
+            let mut tasks = load_tasks()?;
+            let original_len = tasks.len(); // Remember how many tasks we had, like remove_task() does
+            if all {
+                if tasks.is_empty() {
+                    println!("📝 No tasks to clear");
+                    return Ok(());
+                }
+                // Deleting everything can't be undone, so ask first
+                if !confirm(&format!("Delete all {} tasks?", original_len))? {
+                    println!("Nothing deleted");
+                    return Ok(());
+                }
+                tasks.clear(); // Remove every element from the vector
+            } else {
+                tasks.retain(|t| !t.completed); // Keep only the tasks that are not completed
+            }
+            let removed = original_len - tasks.len();
+            // Only write the file if something was actually removed
+            if removed > 0 {
+                save_tasks(&tasks)?;
+            }
+            if all {
+                println!("🧹 Cleared all {} tasks", removed);
+            } else {
+                println!("🧹 Cleared {} completed tasks", removed);
+            }
+        },
+        // User use the 'apply' command. Read operations from stdin and run them all in one go
+        Commands::Apply { keep_going: _, fail_fast } => { // --keep-going is the default, so only --fail-fast changes anything
+            let mut tasks = load_tasks()?; // Load once for the whole batch
+            let mut changed = false; // Only save if at least one operation worked
+            let mut succeeded = 0; // Count results for the summary at the end
+            let mut failed = 0;
+            // lines() gives an iterator over stdin, one line at a time, until the input ends
+            // enumerate() adds a counter so we can report line numbers (starting at 0)
+            for (index, line) in io::stdin().lines().enumerate() {
+                let line = line?; // Each line is a Result because reading can fail
+                let line_number = index + 1; // Humans count lines from 1
+                // Skip empty lines
+                if line.trim().is_empty() {
+                    continue;
+                }
+                // Parse the line into an Operation. A bad line is reported, but doesn't stop the batch (unless --fail-fast)
+                let result = match serde_json::from_str::<Operation>(&line) {
+                    Ok(operation) => apply_operation(&mut tasks, operation),
+                    Err(error) => Err(format!("invalid operation: {}", error)),
+                };
+                match result {
+                    Ok((message, line_changed)) => {
+                        // `||` keeps `changed` true once any line changed something
+                        changed = changed || line_changed;
+                        succeeded += 1;
+                        println!("line {}: ✅ {}", line_number, message);
+                    },
+                    Err(message) => {
+                        failed += 1;
+                        println!("line {}: ❌ {}", line_number, message);
+                        if fail_fast {
+                            println!("stopping at the first error (--fail-fast)");
+                            break;
+                        }
+                    },
+                }
+            }
+            // Write the file a single time at the end, and only if something actually changed
+            if changed {
+                save_tasks(&tasks)?;
+            } else {
+                println!("no changes");
+            }
+            println!("{} succeeded, {} failed", succeeded, failed);
+            // Returning an error makes doit exit with a non-zero code, so scripts can notice the failures
+            if failed > 0 {
+                return Err(DoitError::BatchFailed(format!("{} operation(s) failed", failed)).into());
+            }
+        },
+        // User use the 'rpc' command. Keep running and answer one request per stdin line until the input ends
+        Commands::Rpc => {
+            let mut stdout = io::stdout();
+            for line in io::stdin().lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                // Each response is one line of compact JSON. flush() sends it right away,
+                // otherwise the editor could wait forever for an answer stuck in the buffer
+                writeln!(stdout, "{}", handle_rpc_request(&line))?;
+                stdout.flush()?;
+            }
+        },
+        // User use the 'import' command. Turn the lines of a text file into tasks
+        Commands::Import { path } => {
+            let text = fs::read_to_string(&path).map_err(|e| io_error_with_path(e, "read", Path::new(&path)))?;
+            let mut tasks = load_tasks()?;
+            let mut imported = 0;
+            for line in text.lines() {
+                let description = line.trim(); // Also drops the '\r' of Windows line endings
+                if description.is_empty() {
+                    continue; // Blank lines separate notes, they aren't tasks
+                }
+                add_task(&mut tasks, description.to_string()); // Same as `doit add`, with the next free ID
+                imported += 1;
+            }
+            if imported > 0 {
+                save_tasks(&tasks)?; // One save for the whole file
+            }
+            println!("📥 Imported {} task(s) from {}", imported, path);
+        },
+        // User use the 'export' command. Print all tasks in the chosen format
+        Commands::Export { format } => {
+            let tasks = load_tasks()?;
+            match format {
+                ExportFormat::Org => print!("{}", tasks_to_org(&tasks)),
+                ExportFormat::Markdown => print!("{}", tasks_to_markdown(&tasks)),
+            }
+        },
+        // User use the 'diff' command with two file paths. Compare them
+        Commands::Diff { file_a, file_b } => {
+            // load_tasks_from() treats a missing file as an empty list, which would make
+            // a typo look like "every task was removed". So we check first.
+            for path in [&file_a, &file_b] {
+                if !Path::new(path).exists() {
+                    return Err(DoitError::NotFound(format!("file not found: {}", path)).into());
+                }
+            }
+            let old_tasks = load_tasks_from(Path::new(&file_a))?;
+            let new_tasks = load_tasks_from(Path::new(&file_b))?;
+
+            // Collect every ID that appears in either file, sorted
+            let ids: std::collections::BTreeSet<u32> = old_tasks.iter()
+                .chain(new_tasks.iter()) // Walk through both lists as if they were one
+                .map(|t| t.id)
+                .collect();
+
+            println!("🔍 Comparing {} -> {}", file_a, file_b);
+            let mut differences = 0; // Count how many tasks differ
+            for id in ids {
+                // Find the task with this ID in each file (None if it's not there)
+                let old = old_tasks.iter().find(|t| t.id == id);
+                let new = new_tasks.iter().find(|t| t.id == id);
+                match (old, new) {
+                    // Only in the new file: it was added
+                    (None, Some(task)) => {
+                        println!("  {}", paint(&format!("+ [{}] {}", task.id, task.description), "32")); // green
+                        differences += 1;
+                    },
+                    // Only in the old file: it was removed
+                    (Some(task), None) => {
+                        println!("  {}", paint(&format!("- [{}] {}", task.id, task.description), "31")); // red
+                        differences += 1;
+                    },
+                    // In both files: show the fields that changed, if any
+                    (Some(old), Some(new)) => {
+                        let changes = task_field_changes(old, new);
+                        if !changes.is_empty() {
+                            println!("  {}", paint(&format!("~ [{}] {}", new.id, new.description), "33")); // yellow
+                            for change in changes {
+                                println!("      {}", change);
+                            }
+                            differences += 1;
+                        }
+                    },
+                    // Can't happen: every ID came from at least one of the files
+                    (None, None) => {},
+                }
+            }
+            if differences == 0 {
+                println!("  No differences");
+            }
+        },
+        // User use the 'template' command. Save, apply or list templates
+        // User use the 'tags' command. Show the tags in use, or rename one everywhere
+        Commands::Tags { rename } => {
+            let mut tasks = load_tasks()?;
+            let config = load_config()?;
+            // clap makes sure --rename has exactly two values
+            if let Some([old, new]) = rename.as_deref() {
+                let (old, new) = (config.normalize_tag(old), config.normalize_tag(new));
+                let mut updated = 0;
+                for task in tasks.iter_mut() {
+                    if !task.tags.iter().any(|t| config.normalize_tag(t) == old) {
+                        continue;
+                    }
+                    // Take the old tag out, then add the new one unless the task already has it
+                    task.tags.retain(|t| config.normalize_tag(t) != old);
+                    if !task.tags.contains(&new) {
+                        task.tags.push(new.clone());
+                    }
+                    updated += 1;
+                }
+                if updated == 0 {
+                    return Err(DoitError::NotFound(format!("no task is tagged #{}", old)).into());
+                }
+                save_tasks(&tasks)?;
+                println!("🏷️  Renamed #{} to #{} on {} task(s)", old, new, updated);
+                return Ok(());
+            }
+            // No --rename: count how many tasks use each tag. BTreeMap keeps the tags sorted
+            let mut counts: std::collections::BTreeMap<String, usize> = std::collections::BTreeMap::new();
+            for tag in tasks.iter().flat_map(|t| &t.tags) {
+                *counts.entry(config.normalize_tag(tag)).or_default() += 1;
+            }
+            if counts.is_empty() {
+                println!("🏷️  No tags yet");
+            }
+            for (tag, count) in counts {
+                println!("  #{} ({})", tag, count);
+            }
+        },
+        // User use the 'config' command. Only `config init` for now
+        Commands::Config { action } => match action {
+            ConfigAction::Init { force } => {
+                let path = config_path();
+                // Don't throw away settings the user already wrote
+                if path.exists() && !force {
+                    return Err(DoitError::InvalidInput(format!(
+                        "{} already exists (use --force to overwrite it)", path.display()
+                    )).into());
+                }
+                if let Some(folder) = path.parent() {
+                    fs::create_dir_all(folder).map_err(|e| io_error_with_path(e, "create", folder))?;
+                }
+                write_atomically(&path, SAMPLE_CONFIG.as_bytes())?;
+                println!("📝 Wrote {}", path.display());
+            },
+        },
+        Commands::Template { action } => match action {
+            TemplateAction::Save { name } => {
+                let Some(path) = template_path(&name) else { // let-else: leave early if the name isn't valid
+                    return Err(invalid_template_name().into());
+                };
+                let tasks = load_tasks()?;
+                if tasks.is_empty() {
+                    println!("📝 No tasks to save in a template");
+                    return Ok(());
+                }
+                fs::create_dir_all(templates_dir())?; // Create the folder (and its parents) the first time
+                save_tasks_to(&path, &tasks)?;
+                println!("💾 Saved {} task(s) as template '{}' ({})", tasks.len(), name, path.display());
+            },
+            TemplateAction::Apply { name } => {
+                let Some(path) = template_path(&name) else {
+                    return Err(invalid_template_name().into());
+                };
+                if !path.exists() {
+                    return Err(DoitError::NotFound(format!("template '{}' not found", name)).into());
+                }
+                let mut tasks = load_tasks()?;
+                let template = load_tasks_from(&path)?;
+                // Add each template task as a brand new, incomplete task with a fresh ID.
+                // Everything the user set is copied; ID, completion and timestamps start fresh
+                for task in &template {
+                    let new_task = add_task(&mut tasks, task.description.clone());
+                    new_task.estimate_minutes = task.estimate_minutes;
+                    new_task.url = task.url.clone();
+                    new_task.due = task.due.clone();
+                    new_task.priority = task.priority;
+                    new_task.tags = task.tags.clone();
+                }
+                save_tasks(&tasks)?; // Save once after adding them all
+                println!("✅ Added {} task(s) from template '{}'", template.len(), name);
+            },
+            TemplateAction::List => {
+                let mut names = Vec::new();
+                // read_dir() fails if the folder doesn't exist yet - that just means there are no templates
+                if let Ok(entries) = fs::read_dir(templates_dir()) {
+                    for entry in entries.flatten() { // flatten() skips entries that couldn't be read
+                        let path = entry.path();
+                        // Keep only *.json files and use the file name without extension as the template name
+                        // `&& let` (a "let chain") only runs the block if both conditions match
+                        if path.extension().is_some_and(|ext| ext == "json")
+                            && let Some(stem) = path.file_stem()
+                        {
+                            names.push(stem.to_string_lossy().to_string());
+                        }
+                    }
+                }
+                if names.is_empty() {
+                    println!("📝 No templates yet! Save one with: doit template save <name>");
+                } else {
+                    names.sort(); // read_dir() returns files in no particular order
+                    println!("📋 Templates:");
+                    for name in names {
+                        println!("  {}", name);
+                    }
+                }
+            },
+        },
+        // User use the 'reword' command. Let the AI clean up task descriptions
+        Commands::Reword { id, all, dry_run, concurrency, model } => {
+            let mut tasks = load_tasks()?;
+            // Pick the tasks to reword: every pending task with --all, otherwise the one with the given ID
+            // We collect (id, description) pairs so we don't hold references into `tasks` while we change it later
+            let targets: Vec<(u32, String)> = tasks.iter()
+                .filter(|t| if all { !t.completed } else { Some(t.id) == id })
+                .map(|t| (t.id, t.description.clone()))
+                .collect();
+            if targets.is_empty() {
+                match id {
+                    Some(id) => return Err(task_not_found(id).into()),
+                    None => println!("📝 No pending tasks to reword"), // Nothing to do isn't an error
+                }
+                return Ok(());
+            }
+
+            // Ask the AI for all the rewrites, but only `concurrency` requests at a time,
+            // so a single local GPU isn't flooded with requests.
+            // stream::iter() turns the Vec into a stream, map() creates one future per task,
+            // and buffer_unordered(n) runs at most n of them at once, yielding results as they finish
+            println!("⏳ Asking the AI to reword {} task(s)...", targets.len());
+            // One HTTP client shared by all requests. We take a reference so each `async move` block copies the reference, not the client
+            check_ollama_url()?; // A clear message now instead of one failed request per task
+            let client = &build_client(None, Some(DEFAULT_CONNECT_TIMEOUT))?;
+            let model = model.as_str(); // A &str can be copied into every `async move` block, just like the client reference
+            let mut proposals: Vec<(u32, String, Result<String, String>)> = futures_util::stream::iter(targets)
+                .map(|(task_id, description)| async move {
+                    // Turn the error into a String so each result can be reported on its own later
+                    let proposal = reword_description(client, model, &description).await.map_err(|e| e.to_string());
+                    (task_id, description, proposal)
+                })
+                .buffer_unordered(concurrency.max(1)) // .max(1) so --concurrency 0 still makes progress
+                .collect()
+                .await;
+            // Answers come back in whatever order the AI finished them.
+            // Sorting by ID makes the review (and what gets applied) deterministic
+            proposals.sort_by_key(|(task_id, _, _)| *task_id);
+
+            let mut changed = false; // Only save if at least one rewrite was accepted
+            for (task_id, description, proposal) in proposals {
+                // A failed request is reported, but doesn't stop the other rewrites
+                let proposal = match proposal {
+                    Ok(proposal) => proposal,
+                    Err(error) => {
+                        println!("❌ [{}] Could not reword: {}", task_id, error);
+                        continue;
+                    },
+                };
+                // Nothing to do if the AI gave back the same text (or nothing at all)
+                if proposal.is_empty() || proposal == description {
+                    println!("✔️  [{}] {} (no change suggested)", task_id, description);
+                    continue;
+                }
+                println!("✏️  [{}] {}\n   -> {}", task_id, description, proposal);
+                // In dry-run mode we only show the proposal
+                if dry_run || !confirm("Apply this rewrite?")? {
+                    continue;
+                }
+                if let Some(task) = tasks.iter_mut().find(|t| t.id == task_id) {
+                    task.description = proposal;
+                    changed = true;
+                }
+            }
+            if changed {
+                save_tasks(&tasks)?; // Save all accepted rewrites at once
+            } else if !dry_run {
+                println!("no changes");
             }
         },
         // User use the 'ask' command with a parameter. Ask AI 
-        Commands::Ask { prompt, chat } => {
-            // Validate that user provided a non-empty question
-            if prompt.trim().is_empty() {
-                println!("Error: Please provide a question");
-                return Ok(()); // Return early without calling AI
+        Commands::Ask { prompt, chat, continue_chat, session, reset, template, save_template, args } => {
+            // --reset: forget the saved chat and stop here
+            if reset {
+                let path = last_chat_path();
+                if path.exists() {
+                    fs::remove_file(&path)?;
+                    println!("🧹 Cleared the saved chat session");
+                } else {
+                    println!("📝 No saved chat session");
+                }
+                return Ok(());
+            }
+            // clap makes sure the prompt is there when --reset or --template isn't used
+            let prompt = prompt.unwrap_or_default();
+            // --save-template: store the prompt for later and stop here
+            if let Some(name) = save_template {
+                let path = prompt_template_path(&name).ok_or_else(invalid_template_name)?;
+                if let Some(folder) = path.parent() {
+                    fs::create_dir_all(folder)?;
+                }
+                fs::write(&path, &prompt)?;
+                println!("💾 Saved prompt template '{}'", name);
+                return Ok(());
+            }
+            // Validate that user provided a non-empty question (a template can work without one)
+            if prompt.trim().is_empty() && template.is_none() {
+                return Err(DoitError::InvalidInput("please provide a question".to_string()).into()); // Return early without calling AI
+            }
+
+            // A one-shot question is a single prompt, not a list of messages, so there is no message to put the tasks in.
+            // Fail instead of quietly ignoring the flag
+            if args.context_as_message.is_some() && !(chat || continue_chat || session.is_some()) {
+                return Err(context_as_message_needs_chat().into());
+            }
+
+            // Collect the output options in one place
+            let mut options = build_ask_options(args)?;
+            // --template: load the saved prompt. ask_once() fills in the placeholders
+            if let Some(name) = template {
+                let path = prompt_template_path(&name).ok_or_else(invalid_template_name)?;
+                let text = fs::read_to_string(&path)
+                    .map_err(|_| DoitError::NotFound(format!("no prompt template named '{}' (save one with --save-template)", name)))?;
+                options.prompt_template = Some(text);
+            }
+
+            // Load current tasks as JSON string for AI context
+            let tasks_json = task_context_json(&options)?;
+
+            // --continue: load the last chat and keep going with the new question
+            if continue_chat {
+                let history = load_chat_session(&last_chat_path());
+                if history.is_none() {
+                    println!("📝 No previous chat to continue, starting a new one\n");
+                }
+                ask_chat(&prompt, &tasks_json, history, &last_chat_path(), &options).await?;
+                return Ok(());
+            }
+
+            // --session FILE: like --continue, but with a conversation of your choice
+            if let Some(path) = session {
+                let history = load_chat_session(&path);
+                if history.is_none() {
+                    // Don't overwrite a file we couldn't read - it might be something else, or a session worth fixing
+                    if path.exists() {
+                        return Err(DoitError::InvalidFile(format!("{} is not a valid chat session", path.display())).into());
+                    }
+                    println!("📝 Starting a new chat session in {}\n", path.display());
+                }
+                ask_chat(&prompt, &tasks_json, history, &path, &options).await?;
+                return Ok(());
             }
-            
-            // Load current tasks from file as JSON string for AI context
-            let tasks_json = load_tasks_as_json();
 
             // Route to appropriate AI function based on chat flag
             // If chat=true: enters conversational mode with history
             // If chat=false: asks one question and exits
-            ask_ai(&prompt, &tasks_json, chat).await?;
+            ask_ai(&prompt, &tasks_json, chat, &options).await?;
+        },
+        // User use the 'summarize' command. Ask AI for an overview with a ready-made question
+        Commands::Summarize { args } => {
+            if args.context_as_message.is_some() {
+                return Err(context_as_message_needs_chat().into()); // Summarize is always one-shot
+            }
+            let options = build_ask_options(args)?;
+            let tasks_json = task_context_json(&options)?;
+            // Same as `doit ask` in one-shot mode, we just write the question for the user
+            ask_ai(SUMMARIZE_PROMPT, &tasks_json, false, &options).await?;
+        },
+        // User use the 'self-update' command (only available with the self-update feature)
+        #[cfg(feature = "self-update")]
+        Commands::SelfUpdate { yes } => {
+            self_update(yes).await?;
+        }
+    }
+    Ok(()) // Command ended successfully
+}
+
+#[tokio::main] // Needed so we can use async/await inside main()
+async fn main() {
+    // Parse the command-line arguments provided by the user and create a CLI instance.
+    // Example: User types in terminal: `doit add "Buy milk"`
+    //   - `doit` is the program name
+    //   - `add` is the subcommand
+    //   - `"Buy milk"` is the parameter
+    // This line (CLI::parse()) reads all of that, validates it, and stores it in the `cli` variable.
+    // This is where clap does all the work automatically for us.
+    let cli = CLI::parse();
+    let error_json = cli.error_json; // Read the flag before cli.command is moved into run()
+    // Decide about colors once, for the whole program. set() only fails if it was already set, which can't happen here
+    let _ = COLOR_ENABLED.set(color_enabled(cli.color));
+    let _ = DEDUPE_ON_SAVE.set(cli.dedupe_on_save);
+    if cli.profile {
+        // Some(...) switches recording on. lock() can't fail here because nothing else uses it yet
+        if let Ok(mut profile) = PROFILE.lock() {
+            *profile = Some(Profile::default());
+        }
+    }
+
+    let result = run(cli.command).await;
+    print_profile(); // Also report timings when the command failed
+    if let Err(error) = result {
+        if error_json {
+            // Structured error for tools that wrap doit
+            eprintln!("{}", serde_json::json!({ "error": error.to_string(), "kind": error_kind(error.as_ref()) }));
+        } else {
+            eprintln!("Error: {}", error); // Human readable message
+        }
+        std::process::exit(1); // A non-zero exit code tells scripts that something went wrong
+    }
+}
+
+// Tests for LineBuffer, run them with `cargo test`
+// #[cfg(test)] means this module is only compiled for tests, not into the doit binary
+#[cfg(test)]
+mod tests {
+    use super::*; // Bring everything from main.rs (like LineBuffer) into scope
+
+    // "é" is two bytes in UTF-8 (0xC3 0xA9). If a chunk ends between them,
+    // the line must still come out as "é" once the second half arrives, not as �
+    #[test]
+    fn line_buffer_keeps_multibyte_character_split_across_chunks() {
+        let bytes = "café\n".as_bytes();
+        let split = bytes.len() - 2; // Cut right after the first byte of "é"
+        let mut buffer = LineBuffer::new();
+        buffer.push(&bytes[..split]);
+        assert_eq!(buffer.next_line(), None); // No '\n' yet, so no line
+        buffer.push(&bytes[split..]);
+        assert_eq!(buffer.next_line(), Some("café".to_string()));
+        assert_eq!(buffer.next_line(), None);
+    }
+
+    // A long streamed answer: thousands of NDJSON lines arriving in small chunks
+    // that don't line up with the line ends. Every line must come back once, in order
+    #[test]
+    fn line_buffer_returns_thousands_of_lines_in_order() {
+        let lines: Vec<String> = (0..5000)
+            .map(|i| format!("{{\"response\":\"token {} ✓\",\"done\":false}}", i))
+            .collect();
+        let stream: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+
+        let mut buffer = LineBuffer::new();
+        let mut received = Vec::new();
+        // 7 bytes per chunk: chunks end in the middle of lines and in the middle of "✓" (3 bytes)
+        for chunk in stream.as_bytes().chunks(7) {
+            buffer.push(chunk);
+            while let Some(line) = buffer.next_line() {
+                received.push(line);
+            }
         }
+        assert_eq!(received, lines);
     }
-    Ok(()) // Program ended successfully
 }